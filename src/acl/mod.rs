@@ -0,0 +1,618 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::PathBuf,
+    sync::RwLock,
+};
+
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use rand_core::OsRng;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    errors::TransactionError,
+    operation::{reply::Reply, Operation},
+    pattern::PatternSyntax,
+    pubsub::KEYSPACE,
+};
+
+/// The kinds of command a rule can allow.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandClass {
+    Read,
+    Write,
+    Admin,
+    PubSub,
+}
+
+impl CommandClass {
+    pub const ALL: [CommandClass; 4] = [
+        CommandClass::Read,
+        CommandClass::Write,
+        CommandClass::Admin,
+        CommandClass::PubSub,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CommandClass::Read => "read",
+            CommandClass::Write => "write",
+            CommandClass::Admin => "admin",
+            CommandClass::PubSub => "pubsub",
+        }
+    }
+
+    pub fn parse(class: &str) -> Option<Self> {
+        match class {
+            "read" => Some(CommandClass::Read),
+            "write" => Some(CommandClass::Write),
+            "admin" => Some(CommandClass::Admin),
+            "pubsub" => Some(CommandClass::PubSub),
+            _ => None,
+        }
+    }
+}
+
+/// Allows commands of the given classes on a key and everything beneath it, written as
+/// `team_a.*`, or on every key with `*`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Rule {
+    pub prefix: String,
+    pub classes: BTreeSet<CommandClass>,
+}
+
+impl Rule {
+    /// Returns whether the rule allows the class of command on keys starting with the
+    /// segments.
+    fn allows(&self, class: CommandClass, segments: &[&str]) -> bool {
+        if !self.classes.contains(&class) {
+            return false;
+        }
+        match self.prefix.as_str() {
+            "*" => true,
+            prefix => {
+                let prefix = prefix.strip_suffix(".*").unwrap_or(prefix);
+                let prefix: Vec<&str> = prefix.split('.').collect();
+                segments.starts_with(&prefix)
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct User {
+    password: String,
+    rules: Vec<Rule>,
+}
+
+/// A change to the users or their rules.
+#[derive(Debug, PartialEq)]
+pub enum AclCommand {
+    /// Create a user, or change their password.
+    SetUser(String, String),
+    DelUser(String),
+    /// Allow a user the classes of command on a prefix.
+    Grant(String, String, Vec<CommandClass>),
+    /// Take the classes of command on a prefix away from a user, or all of them if none
+    /// are given.
+    Revoke(String, String, Vec<CommandClass>),
+    List,
+}
+
+impl AclCommand {
+    pub fn parse<'a>(mut parts: impl Iterator<Item = &'a str>) -> Result<Self, TransactionError> {
+        let mut next = |error: TransactionError| parts.next().map(str::to_string).ok_or(error);
+
+        match next(TransactionError::MissingCommand)?.as_str() {
+            "SETUSER" => Ok(AclCommand::SetUser(
+                next(TransactionError::MissingKey)?,
+                next(TransactionError::MissingValue)?,
+            )),
+            "DELUSER" => Ok(AclCommand::DelUser(next(TransactionError::MissingKey)?)),
+            "GRANT" => {
+                let user = next(TransactionError::MissingKey)?;
+                let prefix = parse_prefix(next(TransactionError::MissingKey)?)?;
+                let classes = parse_classes(&next(TransactionError::MissingValue)?)?;
+                Ok(AclCommand::Grant(user, prefix, classes))
+            }
+            "REVOKE" => {
+                let user = next(TransactionError::MissingKey)?;
+                let prefix = parse_prefix(next(TransactionError::MissingKey)?)?;
+                let classes = match next(TransactionError::MissingValue) {
+                    Ok(classes) => parse_classes(&classes)?,
+                    Err(_) => Vec::new(),
+                };
+                Ok(AclCommand::Revoke(user, prefix, classes))
+            }
+            "LIST" => Ok(AclCommand::List),
+            other => Err(TransactionError::UnknownCommand(format!("ACL {}", other))),
+        }
+    }
+}
+
+/// Prefixes are a key followed by `.*`, or `*` for every key.
+fn parse_prefix(prefix: String) -> Result<String, TransactionError> {
+    let key = match prefix.as_str() {
+        "*" => return Ok(prefix),
+        _ => prefix.strip_suffix(".*").unwrap_or(&prefix),
+    };
+    match key.is_empty() || key.contains(['*', '?', '[', '\\']) {
+        true => Err(TransactionError::InvalidKey(prefix)),
+        false => Ok(format!("{}.*", key)),
+    }
+}
+
+fn parse_classes(classes: &str) -> Result<Vec<CommandClass>, TransactionError> {
+    classes
+        .split(',')
+        .map(|class| {
+            CommandClass::parse(class).ok_or(TransactionError::InvalidValue(class.to_string()))
+        })
+        .collect()
+}
+
+/// The users allowed to connect and the rules for what each of them may do. Until the
+/// first user is created every connection may do anything, and that user is allowed
+/// everything.
+pub struct AccessControl {
+    path: Option<PathBuf>,
+    users: RwLock<BTreeMap<String, User>>,
+}
+
+impl Default for AccessControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AccessControl {
+    /// Creates access control that is kept in memory only.
+    pub fn new() -> Self {
+        AccessControl {
+            path: None,
+            users: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Loads the users from a JSON file, which is written whenever they change.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self, TransactionError> {
+        let path = path.into();
+        let users = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|_| TransactionError::SerializationFailure)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(TransactionError::OperationFailure(e.to_string())),
+        };
+
+        Ok(AccessControl {
+            path: Some(path),
+            users: RwLock::new(users),
+        })
+    }
+
+    /// Returns whether connections must authenticate, which they must once any user exists.
+    pub fn is_enabled(&self) -> bool {
+        !self.users.read().unwrap().is_empty()
+    }
+
+    pub fn authenticate(&self, name: &str, password: &str) -> Result<(), TransactionError> {
+        let hash = match self.users.read().unwrap().get(name) {
+            Some(user) => user.password.clone(),
+            None => return Err(TransactionError::AuthenticationFailed),
+        };
+        let hash = PasswordHash::new(&hash).map_err(|_| TransactionError::InternalError)?;
+
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .map_err(|_| TransactionError::AuthenticationFailed)
+    }
+
+    /// Checks that the user may run the operation. Anyone may authenticate or negotiate a
+    /// protocol, while everything else needs a user once access control is enabled.
+    pub fn authorize(
+        &self,
+        user: Option<&str>,
+        operation: &Operation,
+    ) -> Result<(), TransactionError> {
+        if matches!(operation, Operation::Auth(_, _) | Operation::Hello(_)) {
+            return Ok(());
+        }
+
+        let users = self.users.read().unwrap();
+        if users.is_empty() {
+            return Ok(());
+        }
+        let user = user
+            .and_then(|user| users.get(user))
+            .ok_or(TransactionError::AuthenticationRequired)?;
+
+        for (class, key) in requirements(operation) {
+            let segments = literal_segments(key);
+            if !user.rules.iter().any(|rule| rule.allows(class, &segments)) {
+                return Err(TransactionError::PermissionDenied(format!(
+                    "{} access to {} is not allowed",
+                    class.name(),
+                    key.unwrap_or("*")
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn execute(&self, command: AclCommand) -> Result<Reply, TransactionError> {
+        // Hash before taking the lock, as hashing is deliberately slow.
+        let password = match &command {
+            AclCommand::SetUser(_, password) => Some(hash_password(password)?),
+            _ => None,
+        };
+
+        let mut users = self.users.write().unwrap();
+        let mut updated = users.clone();
+        let not_found =
+            |name: &str| TransactionError::OperationFailure(format!("no user named {}", name));
+
+        match command {
+            AclCommand::List => {
+                let list: BTreeMap<&String, serde_json::Value> = users
+                    .iter()
+                    .map(|(name, user)| (name, json!({ "rules": user.rules })))
+                    .collect();
+                return serde_json::to_string(&list)
+                    .map(Reply::Json)
+                    .map_err(|_| TransactionError::SerializationFailure);
+            }
+            AclCommand::SetUser(name, _) => {
+                let password = password.unwrap_or_default();
+                match updated.get_mut(&name) {
+                    Some(user) => user.password = password,
+                    None => {
+                        let rules = match updated.is_empty() {
+                            true => vec![Rule {
+                                prefix: String::from("*"),
+                                classes: CommandClass::ALL.into_iter().collect(),
+                            }],
+                            false => Vec::new(),
+                        };
+                        updated.insert(name, User { password, rules });
+                    }
+                }
+            }
+            AclCommand::DelUser(name) => {
+                updated.remove(&name).ok_or_else(|| not_found(&name))?;
+            }
+            AclCommand::Grant(name, prefix, classes) => {
+                let user = updated.get_mut(&name).ok_or_else(|| not_found(&name))?;
+                match user.rules.iter_mut().find(|rule| rule.prefix == prefix) {
+                    Some(rule) => rule.classes.extend(classes),
+                    None => user.rules.push(Rule {
+                        prefix,
+                        classes: classes.into_iter().collect(),
+                    }),
+                }
+            }
+            AclCommand::Revoke(name, prefix, classes) => {
+                let user = updated.get_mut(&name).ok_or_else(|| not_found(&name))?;
+                for rule in user.rules.iter_mut().filter(|rule| rule.prefix == prefix) {
+                    match classes.is_empty() {
+                        true => rule.classes.clear(),
+                        false => classes.iter().for_each(|class| {
+                            rule.classes.remove(class);
+                        }),
+                    }
+                }
+                user.rules.retain(|rule| !rule.classes.is_empty());
+            }
+        }
+
+        self.save(&updated)?;
+        *users = updated;
+        Ok(Reply::ok())
+    }
+
+    fn save(&self, users: &BTreeMap<String, User>) -> Result<(), TransactionError> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let contents = serde_json::to_string_pretty(users)
+            .map_err(|_| TransactionError::SerializationFailure)?;
+
+        // Write a new file and move it into place, so a failed write leaves the old one.
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, contents)
+            .and_then(|_| fs::rename(&temporary, path))
+            .map_err(|e| TransactionError::OperationFailure(e.to_string()))
+    }
+}
+
+fn hash_password(password: &str) -> Result<String, TransactionError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|_| TransactionError::InternalError)
+}
+
+/// Returns the class of each access the operation needs, with the key, pattern or channel
+/// it needs it on. `None` stands for every key.
+fn requirements(operation: &Operation) -> Vec<(CommandClass, Option<&str>)> {
+    use CommandClass::*;
+
+    match operation {
+        Operation::Get(key)
+        | Operation::GetOrdered(key, _, _)
+        | Operation::Keys(key, PatternSyntax::Glob)
+        | Operation::Scan(key, _, _)
+        | Operation::Query(key, _)
+        | Operation::Find(key, _)
+        | Operation::Aggregate(_, key, _)
+        | Operation::History(key, _)
+        | Operation::GetAsOf(key, _)
+        | Operation::Ttl(key) => vec![(Read, Some(key))],
+        Operation::Range(start, end, _, _) => vec![(Read, range_prefix(start, end))],
+        Operation::Keys(_, PatternSyntax::Regex) | Operation::Info => vec![(Read, None)],
+        Operation::Snapshot(operation) => requirements(operation),
+        Operation::Put(key, _)
+        | Operation::Delete(key)
+        | Operation::Expire(key, _)
+        | Operation::Persist(key)
+        | Operation::Incr(key) => vec![(Write, Some(key))],
+        Operation::ExpireAt(expiration) => vec![(Write, Some(&expiration.key))],
+        Operation::Batch(operations) => operations.iter().flat_map(requirements).collect(),
+        Operation::Rename(from, to) | Operation::Move(from, to) => {
+            vec![(Write, Some(from)), (Write, Some(to))]
+        }
+        Operation::Copy(from, to, _) => vec![(Read, Some(from)), (Write, Some(to))],
+        Operation::Index(pattern) | Operation::DropIndex(pattern) => vec![(Admin, Some(pattern))],
+        Operation::Purge | Operation::Acl(_) => vec![(Admin, None)],
+        Operation::Publish(channel, _) => vec![channel_requirement(channel)],
+        Operation::Subscribe(channels) => channels
+            .iter()
+            .map(|channel| channel_requirement(channel))
+            .collect(),
+        Operation::PSubscribe(patterns) => patterns
+            .iter()
+            .flat_map(|pattern| pattern_requirements(pattern))
+            .collect(),
+        Operation::Time
+        | Operation::SnapshotBegin
+        | Operation::SnapshotEnd
+        | Operation::Unsubscribe(_)
+        | Operation::PUnsubscribe(_)
+        | Operation::Hello(_)
+        | Operation::Auth(_, _) => Vec::new(),
+    }
+}
+
+/// Returns the segments shared by every key a range can return. Keys between two keys
+/// share the text both start with, but only up to its last dot is that text a prefix.
+fn range_prefix<'a>(start: &'a str, end: &str) -> Option<&'a str> {
+    if start == end {
+        return Some(start);
+    }
+    let common = start
+        .char_indices()
+        .zip(end.chars())
+        .find(|((_, a), b)| a != b)
+        .map(|((i, _), _)| i)
+        .unwrap_or(start.len().min(end.len()));
+
+    start[..common].rfind('.').map(|dot| &start[..dot])
+}
+
+/// Keyspace channels reveal changes to keys, so they need read access to the keys.
+fn channel_requirement(channel: &str) -> (CommandClass, Option<&str>) {
+    match channel
+        .strip_prefix(KEYSPACE)
+        .and_then(|key| key.strip_prefix('.'))
+    {
+        Some(key) => (CommandClass::Read, Some(key)),
+        None => (CommandClass::PubSub, Some(channel)),
+    }
+}
+
+/// A pattern can match keyspace channels without naming them, as `*` or `__keyspace_?.*`
+/// do. Unless it names the keys, such a pattern needs read access to every key as well as
+/// access to the other channels it matches.
+fn pattern_requirements(pattern: &str) -> Vec<(CommandClass, Option<&str>)> {
+    let keyspace = format!("{}.", KEYSPACE);
+    let literal = match pattern.find(['*', '?', '[', '\\']) {
+        Some(wildcard) => &pattern[..wildcard],
+        None => return vec![channel_requirement(pattern)],
+    };
+
+    if literal.starts_with(&keyspace) {
+        vec![channel_requirement(pattern)]
+    } else if keyspace.starts_with(literal) {
+        vec![
+            (CommandClass::Read, None),
+            (CommandClass::PubSub, Some(pattern)),
+        ]
+    } else {
+        vec![(CommandClass::PubSub, Some(pattern))]
+    }
+}
+
+/// Returns the segments of a key up to the first one containing a wildcard, as everything
+/// the key can match lies beneath them.
+fn literal_segments(key: Option<&str>) -> Vec<&str> {
+    match key {
+        Some(key) => key
+            .split('.')
+            .take_while(|segment| !segment.contains(['*', '?', '[', '\\']))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(command: &str) -> Operation {
+        Operation::parse(command.to_string()).unwrap()
+    }
+
+    #[test]
+    fn scopes_rules_to_prefixes() {
+        let access = AccessControl::new();
+        for command in [
+            "ACL SETUSER root secret",
+            "ACL SETUSER team-a hunter2",
+            "ACL GRANT team-a team_a.* read,write",
+            "ACL GRANT team-a shared.* read",
+        ] {
+            match parse(command) {
+                Operation::Acl(command) => {
+                    assert_eq!(access.execute(command), Ok(Reply::ok()))
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        let user = Some("team-a");
+        for allowed in [
+            "PUT team_a.users.1 1",
+            "GET team_a.*",
+            "KEYS team_a.*.email",
+            "GET shared.config",
+            "RANGE team_a.a team_a.zzz",
+            "COPY shared.config team_a.config",
+            "SUBSCRIBE __keyspace__.team_a.users.1",
+            "TIME",
+        ] {
+            assert_eq!(
+                access.authorize(user, &parse(allowed)),
+                Ok(()),
+                "{}",
+                allowed
+            );
+        }
+        for denied in [
+            "PUT shared.config 1",
+            "GET team_ab.users",
+            "RANGE team_a.a team_b.zzz",
+            "RANGE team_a team_a.zzz",
+            "GET *",
+            "KEYS team_*",
+            "PURGE",
+            "PUBLISH alerts hi",
+            "ACL LIST",
+        ] {
+            assert!(
+                matches!(
+                    access.authorize(user, &parse(denied)),
+                    Err(TransactionError::PermissionDenied(_))
+                ),
+                "{}",
+                denied
+            );
+        }
+
+        assert_eq!(access.authorize(Some("root"), &parse("PURGE")), Ok(()));
+        assert_eq!(
+            access.authorize(None, &parse("GET team_a.*")),
+            Err(TransactionError::AuthenticationRequired)
+        );
+        assert_eq!(
+            access.authorize(None, &parse("AUTH team-a hunter2")),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn checks_keyspace_patterns_against_read_rules() {
+        let access = AccessControl::new();
+        for command in [
+            "ACL SETUSER root secret",
+            "ACL SETUSER team-a hunter2",
+            "ACL GRANT team-a team_a.* read",
+            "ACL GRANT team-a * pubsub",
+        ] {
+            match parse(command) {
+                Operation::Acl(command) => access.execute(command).map(|_| ()).unwrap(),
+                _ => panic!("{} is not an ACL command", command),
+            }
+        }
+
+        let user = Some("team-a");
+        for allowed in ["PSUBSCRIBE __keyspace__.team_a.*", "PSUBSCRIBE alerts.*"] {
+            assert_eq!(
+                access.authorize(user, &parse(allowed)),
+                Ok(()),
+                "{}",
+                allowed
+            );
+        }
+        for denied in [
+            "PSUBSCRIBE __keyspace__.team_b.*",
+            "PSUBSCRIBE __keyspace__*",
+            "PSUBSCRIBE __keyspace_?.team_b.*",
+            "PSUBSCRIBE *",
+        ] {
+            assert!(
+                matches!(
+                    access.authorize(user, &parse(denied)),
+                    Err(TransactionError::PermissionDenied(_))
+                ),
+                "{}",
+                denied
+            );
+        }
+        assert_eq!(
+            access.authorize(Some("root"), &parse("PSUBSCRIBE *")),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn authenticates_and_persists_users() {
+        let path = "acl.test.json";
+        fs::remove_file(path).unwrap_or(());
+
+        let access = AccessControl::load(path).unwrap();
+        assert!(!access.is_enabled());
+        access
+            .execute(AclCommand::SetUser(
+                "admin".to_string(),
+                "secret".to_string(),
+            ))
+            .unwrap();
+        access
+            .execute(AclCommand::Grant(
+                "admin".to_string(),
+                "team_a.*".to_string(),
+                vec![CommandClass::Read],
+            ))
+            .unwrap();
+        access
+            .execute(AclCommand::Revoke(
+                "admin".to_string(),
+                "*".to_string(),
+                Vec::new(),
+            ))
+            .unwrap();
+
+        let access = AccessControl::load(path).unwrap();
+        assert!(access.is_enabled());
+        assert_eq!(access.authenticate("admin", "secret"), Ok(()));
+        assert_eq!(
+            access.authenticate("admin", "wrong"),
+            Err(TransactionError::AuthenticationFailed)
+        );
+        assert_eq!(
+            access.execute(AclCommand::List),
+            Ok(Reply::Json(
+                r#"{"admin":{"rules":[{"classes":["read"],"prefix":"team_a.*"}]}}"#.to_string()
+            ))
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+}
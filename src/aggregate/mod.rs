@@ -0,0 +1,151 @@
+use crate::{errors::TransactionError, operation::value::Value};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Aggregate {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg,
+}
+
+impl Aggregate {
+    pub fn parse(command: &str) -> Option<Self> {
+        match command {
+            "COUNT" => Some(Aggregate::Count),
+            "SUM" => Some(Aggregate::Sum),
+            "MIN" => Some(Aggregate::Min),
+            "MAX" => Some(Aggregate::Max),
+            "AVG" => Some(Aggregate::Avg),
+            _ => None,
+        }
+    }
+
+    /// Computes the aggregate over the given keys and values. Values that are not integers
+    /// or floats are skipped, or rejected if `strict` is set. `COUNT` counts every value.
+    pub fn compute<'a>(
+        &self,
+        values: impl IntoIterator<Item = (&'a String, &'a Value)>,
+        strict: bool,
+    ) -> Result<Value, TransactionError> {
+        let mut count: i64 = 0;
+        let mut integer_sum: Option<i64> = Some(0);
+        let mut float_sum = 0.0;
+        let mut min: Option<Value> = None;
+        let mut max: Option<Value> = None;
+
+        for (key, value) in values {
+            let number = match value {
+                Value::Integer(number) => *number as f64,
+                Value::Float(number) => *number,
+                _ if *self == Aggregate::Count => {
+                    count += 1;
+                    continue;
+                }
+                _ if strict => {
+                    return Err(TransactionError::OperationFailure(format!(
+                        "{} is not a number",
+                        key
+                    )))
+                }
+                _ => continue,
+            };
+
+            count += 1;
+            float_sum += number;
+            integer_sum = match (integer_sum, value) {
+                (Some(sum), Value::Integer(number)) => sum.checked_add(*number),
+                _ => None,
+            };
+            if !matches!(&min, Some(min) if number >= as_f64(min)) {
+                min = Some(value.clone());
+            }
+            if !matches!(&max, Some(max) if number <= as_f64(max)) {
+                max = Some(value.clone());
+            }
+        }
+
+        Ok(match self {
+            Aggregate::Count => Value::Integer(count),
+            Aggregate::Sum => match integer_sum {
+                Some(sum) => Value::Integer(sum),
+                None => Value::Float(float_sum),
+            },
+            Aggregate::Min => min.unwrap_or(Value::Null),
+            Aggregate::Max => max.unwrap_or(Value::Null),
+            Aggregate::Avg if count == 0 => Value::Null,
+            Aggregate::Avg => Value::Float(float_sum / count as f64),
+        })
+    }
+}
+
+fn as_f64(value: &Value) -> f64 {
+    match value {
+        Value::Integer(number) => *number as f64,
+        Value::Float(number) => *number,
+        _ => f64::NAN,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entries(values: Vec<Value>) -> Vec<(String, Value)> {
+        values
+            .into_iter()
+            .enumerate()
+            .map(|(i, value)| (format!("key.{}", i), value))
+            .collect()
+    }
+
+    fn compute(aggregate: Aggregate, values: &[(String, Value)], strict: bool) -> Value {
+        aggregate
+            .compute(values.iter().map(|(k, v)| (k, v)), strict)
+            .unwrap()
+    }
+
+    #[test]
+    fn aggregates_numbers() {
+        let values = entries(vec![
+            Value::Integer(4),
+            Value::Float(1.5),
+            Value::String("ignored".to_string()),
+            Value::Integer(-2),
+        ]);
+
+        assert_eq!(compute(Aggregate::Count, &values, false), Value::Integer(4));
+        assert_eq!(compute(Aggregate::Sum, &values, false), Value::Float(3.5));
+        assert_eq!(compute(Aggregate::Min, &values, false), Value::Integer(-2));
+        assert_eq!(compute(Aggregate::Max, &values, false), Value::Integer(4));
+        assert_eq!(
+            compute(Aggregate::Avg, &values, false),
+            Value::Float(3.5 / 3.0)
+        );
+    }
+
+    #[test]
+    fn sums_integers_as_integer() {
+        let values = entries(vec![Value::Integer(4), Value::Integer(6)]);
+        assert_eq!(compute(Aggregate::Sum, &values, false), Value::Integer(10));
+    }
+
+    #[test]
+    fn aggregates_empty_input() {
+        assert_eq!(compute(Aggregate::Sum, &[], false), Value::Integer(0));
+        assert_eq!(compute(Aggregate::Min, &[], false), Value::Null);
+        assert_eq!(compute(Aggregate::Avg, &[], false), Value::Null);
+    }
+
+    #[test]
+    fn rejects_non_numbers_when_strict() {
+        let values = entries(vec![Value::Integer(4), Value::Boolean(true)]);
+        assert_eq!(
+            Aggregate::Sum.compute(values.iter().map(|(k, v)| (k, v)), true),
+            Err(TransactionError::OperationFailure(
+                "key.1 is not a number".to_string()
+            ))
+        );
+        assert_eq!(compute(Aggregate::Count, &values, true), Value::Integer(2));
+    }
+}
@@ -71,6 +71,15 @@ where
     map: HashMap<String, Arc<HeapData<T>>>,
 }
 
+impl<T> Default for AtomicHeap<T>
+where
+    T: Ord + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T> AtomicHeap<T>
 where
     T: Ord + Clone,
@@ -105,6 +114,11 @@ where
         None
     }
 
+    /// Returns the top entry without discarding invalidated entries, so it may be stale.
+    pub fn peek_unchecked(&self) -> Option<&T> {
+        self.heap.peek().map(|heap_data| &heap_data.data)
+    }
+
     pub fn pop(&mut self) -> Option<T> {
         while let Some(heap_data) = self.heap.pop() {
             if *heap_data.valid.lock().unwrap() {
@@ -124,6 +138,26 @@ where
         self.map.remove(key);
     }
 
+    pub fn get(&self, key: &str) -> Option<T> {
+        self.map.get(key).map(|heap_data| heap_data.data.clone())
+    }
+
+    /// Invalidates the pending entry for the key and returns its data.
+    pub fn remove(&mut self, key: &str) -> Option<T> {
+        let heap_data = self.map.remove(key)?;
+        *heap_data.valid.lock().unwrap() = false;
+        Some(heap_data.data.clone())
+    }
+
+    /// Returns the number of keys with a pending entry.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
     pub fn clear(&mut self) {
         self.heap.clear();
         self.map.clear();
@@ -143,6 +177,7 @@ mod test {
         heap.push("b".to_string(), 4);
         heap.push("c".to_string(), 5);
 
+        assert_eq!(heap.len(), 3);
         assert_eq!(heap.peek(), Some(5));
         assert_eq!(heap.pop(), Some(5));
         assert_eq!(heap.peek(), Some(4));
@@ -151,5 +186,20 @@ mod test {
         assert_eq!(heap.pop(), Some(3));
         assert_eq!(heap.peek(), None);
         assert_eq!(heap.pop(), None);
+        assert!(heap.is_empty());
+    }
+
+    #[test]
+    fn removes_pending_entries() {
+        let mut heap = AtomicHeap::new();
+        heap.push("a".to_string(), 1);
+        heap.push("b".to_string(), 2);
+
+        assert_eq!(heap.get("b"), Some(2));
+        assert_eq!(heap.remove("b"), Some(2));
+        assert_eq!(heap.get("b"), None);
+        assert_eq!(heap.remove("b"), None);
+        assert_eq!(heap.pop(), Some(1));
+        assert_eq!(heap.pop(), None);
     }
 }
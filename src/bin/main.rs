@@ -1,14 +1,24 @@
 use clap::Parser;
 use directories::ProjectDirs;
-use myco_kv::{kvmap::KVMap, wal::WriteAheadLog, worker::Worker};
+use myco_kv::{
+    acl::AccessControl,
+    clock::{Clock, SystemClock},
+    history,
+    kvmap::KVMap,
+    operation::timeunit,
+    wal::WriteAheadLog,
+    worker::Worker,
+};
 use std::{
     fs,
-    sync::{Arc, Mutex},
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
     thread,
 };
 
 mod repl;
 mod server;
+mod tls;
 
 #[derive(Parser, Debug)]
 #[command(name = "MycoKV", version = "0.1.0", author = "WVAviator")]
@@ -18,12 +28,91 @@ struct Args {
 
     #[clap(long, action)]
     purge: bool,
+
+    /// The maximum number of commands processed at once. Defaults to the number of CPUs.
+    #[arg(long)]
+    workers: Option<usize>,
+
+    /// The number of previous values kept for each key, or 0 to keep no history.
+    #[arg(long, default_value_t = history::DEFAULT_LIMIT)]
+    history: usize,
+
+    /// How long the history of a deleted key is kept, such as `30m` or `1h`.
+    #[arg(long, default_value = "1h", value_parser = parse_retention)]
+    history_retention: i64,
+
+    /// Also accept Redis clients, speaking RESP2 and RESP3, on this port.
+    #[arg(long)]
+    resp_port: Option<u16>,
+
+    /// Also serve keys over HTTP on this port.
+    #[arg(long)]
+    http_port: Option<u16>,
+
+    /// Only accept TLS connections, presenting this PEM certificate.
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// The PEM private key for the TLS certificate.
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Require clients to present a certificate signed by this PEM authority.
+    #[arg(long, requires = "tls_cert")]
+    tls_client_ca: Option<PathBuf>,
+
+    /// The PEM authority the REPL trusts to sign the server's certificate. Defaults to
+    /// the certificate itself, for self-signed certificates.
+    #[arg(long, requires = "tls_cert")]
+    tls_ca: Option<PathBuf>,
+
+    /// The PEM certificate the REPL presents when client certificates are required.
+    #[arg(long, requires_all = ["tls_client_key", "tls_client_ca"])]
+    tls_client_cert: Option<PathBuf>,
+
+    /// The PEM private key for the REPL's client certificate.
+    #[arg(long, requires = "tls_client_cert")]
+    tls_client_key: Option<PathBuf>,
+
+    /// Also accept connections on a Unix socket at this path.
+    #[arg(long)]
+    socket: Option<PathBuf>,
+
+    /// The octal permissions of the Unix socket, which decide who may connect.
+    #[arg(long, default_value = "660", value_parser = parse_mode, requires = "socket")]
+    socket_mode: u32,
+
+    /// Only accept connections on the Unix socket, without listening on a port.
+    #[arg(long, action, requires = "socket")]
+    no_tcp: bool,
+}
+
+fn parse_mode(mode: &str) -> Result<u32, String> {
+    match u32::from_str_radix(mode, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(format!("{} is not an octal file mode", mode)),
+    }
+}
+
+fn parse_retention(retention: &str) -> Result<i64, String> {
+    match timeunit::parse_duration(retention) {
+        Some(retention) if retention >= 0 => Ok(retention),
+        _ => Err(format!("{} is not a duration", retention)),
+    }
 }
 
 fn main() {
     let args = Args::parse();
-    let port = args.port.unwrap();
+    let port = match args.no_tcp {
+        true => None,
+        false => args.port,
+    };
     let purge = args.purge;
+    let workers = args.workers.unwrap_or_else(|| {
+        thread::available_parallelism()
+            .map(|parallelism| parallelism.get())
+            .unwrap_or(1)
+    });
 
     let system_data_directory = ProjectDirs::from("com", "WVAviator", "MycoKV")
         .expect("Could not access system data directory.");
@@ -42,27 +131,68 @@ fn main() {
     }
 
     let wal = Arc::new(Mutex::new(wal));
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
 
-    let mut kvmap = KVMap::new(wal);
+    let mut kvmap = KVMap::with_clock(wal, Arc::clone(&clock));
+    kvmap.set_history(args.history, args.history_retention);
     kvmap
         .restore()
         .expect("Could not restore database from log.");
 
-    let kvmap = Arc::new(Mutex::new(kvmap));
+    let kvmap = Arc::new(RwLock::new(kvmap));
 
     let worker_kvmap = Arc::clone(&kvmap);
     let expiration_worker = move || {
-        let mut kvmap = worker_kvmap.lock().unwrap();
-        kvmap.process_expirations().unwrap_or(());
+        if worker_kvmap.read().unwrap().has_due_expirations() {
+            let mut kvmap = worker_kvmap.write().unwrap();
+            kvmap.process_expirations().unwrap_or(());
+        }
     };
-    let expiration_worker = Worker::new(5000, expiration_worker);
+    let expiration_worker = Worker::with_clock(5000, expiration_worker, clock);
     let expiration_worker_thread = expiration_worker.start();
 
+    let access = AccessControl::load(system_data_directory.join("acl.json"))
+        .expect("Could not load users and access rules.");
+    let access = Arc::new(access);
+
+    let (server_tls, repl_tls) = match (args.tls_cert, args.tls_key) {
+        (Some(cert), Some(key)) => {
+            let client = tls::ClientFiles {
+                ca: args.tls_ca.unwrap_or_else(|| cert.clone()),
+                identity: args.tls_client_cert.zip(args.tls_client_key),
+            };
+            let server = tls::ServerFiles {
+                cert,
+                key,
+                client_ca: args.tls_client_ca,
+            };
+            (
+                Some(tls::server_config(&server).expect("Could not load TLS certificate.")),
+                Some(tls::client_config(&client).expect("Could not load TLS certificate.")),
+            )
+        }
+        _ => (None, None),
+    };
+
     let server_kvmap = Arc::clone(&kvmap);
+    let listeners = server::Listeners {
+        resp_port: args.resp_port,
+        http_port: args.http_port,
+        socket: args.socket.clone().map(|path| server::SocketFile {
+            path,
+            mode: args.socket_mode,
+        }),
+        tls: server_tls,
+    };
+    // The REPL prefers the socket, as it is on the same host.
+    let address = match (args.socket, port) {
+        (Some(path), _) => repl::Address::Socket(path),
+        (None, port) => repl::Address::Tcp(port.unwrap(), repl_tls),
+    };
     let server_thread = thread::spawn(move || {
-        server::start(port, server_kvmap);
+        server::start(port, listeners, server_kvmap, access, workers);
     });
-    let repl_thread = thread::spawn(move || repl::start(port));
+    let repl_thread = thread::spawn(move || repl::start(address));
 
     server_thread.join().unwrap();
     repl_thread.join().unwrap();
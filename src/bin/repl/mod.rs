@@ -1,25 +1,46 @@
 use std::{
-    io::{BufRead, BufReader},
-    net::TcpStream,
+    io::{BufRead, BufReader, IsTerminal},
+    path::PathBuf,
+    sync::Arc,
 };
 
+use rustls::ClientConfig;
+
 mod send;
 
-pub fn start(port: u16) {
+/// The number of requests sent at once when commands are piped in rather than typed.
+const PIPELINE_SIZE: usize = 256;
+
+/// Where the REPL connects to the server: a local port, over TLS if configured, or a
+/// Unix socket.
+pub enum Address {
+    Tcp(u16, Option<Arc<ClientConfig>>),
+    Socket(PathBuf),
+}
+
+pub fn start(address: Address) {
     let stdin = std::io::stdin();
+    let is_terminal = stdin.is_terminal();
     let mut reader = BufReader::new(stdin);
 
-    let addr = format!("localhost:{}", port);
-    let mut stream = TcpStream::connect(&addr).unwrap();
+    let mut connection = match address {
+        Address::Tcp(port, tls) => send::Connection::connect("localhost", port, tls),
+        Address::Socket(path) => send::Connection::connect_socket(&path),
+    }
+    .unwrap();
+
+    if !is_terminal {
+        return send_batches(&mut connection, reader);
+    }
 
     loop {
         let mut buffer = String::new();
-        if let Err(_) = reader.read_line(&mut buffer) {
+        if reader.read_line(&mut buffer).is_err() {
             println!("Unable to read input, please try again.");
             continue;
         }
 
-        match send::send_request(&mut stream, &buffer) {
+        match connection.send_request(&buffer) {
             Ok(response) => {
                 print!("{}", response);
             }
@@ -29,3 +50,27 @@ pub fn start(port: u16) {
         }
     }
 }
+
+/// Pipelines commands piped in from a file or another program, without waiting for each
+/// response before sending the next command.
+fn send_batches(connection: &mut send::Connection, reader: impl BufRead) {
+    let mut lines = reader
+        .lines()
+        .map_while(Result::ok)
+        .filter(|line| !line.trim().is_empty());
+
+    loop {
+        let batch: Vec<String> = lines.by_ref().take(PIPELINE_SIZE).collect();
+        if batch.is_empty() {
+            return;
+        }
+
+        match connection.send_pipelined(&batch) {
+            Ok(responses) => responses.iter().for_each(|response| print!("{}", response)),
+            Err(e) => {
+                eprintln!("Error occurred communicating with server: {}", e);
+                return;
+            }
+        }
+    }
+}
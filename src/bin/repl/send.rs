@@ -1,15 +1,184 @@
 use std::{
-    io::{prelude::*, BufReader},
+    io::{self, prelude::*, BufReader},
     net::TcpStream,
+    os::unix::net::UnixStream,
+    path::Path,
+    sync::Arc,
 };
 
-pub fn send_request(stream: &mut TcpStream, message: &str) -> Result<String, std::io::Error> {
-    stream.write_all(message.as_bytes())?;
+use rustls::{pki_types::ServerName, ClientConfig, ClientConnection, StreamOwned};
 
-    let mut buf_reader = BufReader::new(stream);
-    let mut response = String::new();
+trait Stream: Read + Write + Send {}
 
-    buf_reader.read_line(&mut response)?;
+impl<T: Read + Write + Send> Stream for T {}
 
-    Ok(response)
+pub struct Connection {
+    reader: BufReader<Box<dyn Stream>>,
+}
+
+impl Connection {
+    /// Connects to the server, over TLS if a configuration is given. The server's
+    /// certificate must be valid for `host`.
+    pub fn connect(host: &str, port: u16, tls: Option<Arc<ClientConfig>>) -> io::Result<Self> {
+        let stream = TcpStream::connect((host, port))?;
+        let stream: Box<dyn Stream> = match tls {
+            Some(config) => {
+                let name = ServerName::try_from(host.to_string())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                let connection = ClientConnection::new(config, name).map_err(io::Error::other)?;
+                Box::new(StreamOwned::new(connection, stream))
+            }
+            None => Box::new(stream),
+        };
+
+        Ok(Connection {
+            reader: BufReader::new(stream),
+        })
+    }
+
+    pub fn connect_socket(path: &Path) -> io::Result<Self> {
+        let stream = UnixStream::connect(path)?;
+        Ok(Connection {
+            reader: BufReader::new(Box::new(stream)),
+        })
+    }
+
+    fn write_all(&mut self, message: &[u8]) -> io::Result<()> {
+        let stream = self.reader.get_mut();
+        stream.write_all(message)?;
+        stream.flush()
+    }
+
+    pub fn send_request(&mut self, message: &str) -> io::Result<String> {
+        self.write_all(message.as_bytes())?;
+
+        let mut response = String::new();
+        self.reader.read_line(&mut response)?;
+
+        Ok(response)
+    }
+
+    /// Sends every request before reading any response. Each request is tagged with its
+    /// position, so the responses can be returned in order even with other lines, such as
+    /// subscription messages, among them.
+    pub fn send_pipelined(&mut self, requests: &[String]) -> io::Result<Vec<String>> {
+        let batch: String = requests
+            .iter()
+            .enumerate()
+            .map(|(id, request)| format!("#{} {}\n", id, request.trim_end()))
+            .collect();
+        self.write_all(batch.as_bytes())?;
+
+        let mut responses = vec![None; requests.len()];
+        let mut remaining = requests.len();
+        while remaining > 0 {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+
+            let tagged = line
+                .strip_prefix('#')
+                .and_then(|line| line.split_once(' '))
+                .and_then(|(id, response)| Some((id.parse::<usize>().ok()?, response)));
+            if let Some((id, response)) = tagged {
+                if let Some(slot @ None) = responses.get_mut(id) {
+                    *slot = Some(response.to_string());
+                    remaining -= 1;
+                }
+            }
+        }
+
+        Ok(responses.into_iter().flatten().collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::TcpListener, thread};
+
+    use super::*;
+    use crate::tls;
+
+    #[test]
+    fn matches_pipelined_responses_to_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut requests = String::new();
+            for _ in 0..2 {
+                reader.read_line(&mut requests).unwrap();
+            }
+            assert_eq!(requests, "#0 GET a\n#1 GET b\n");
+            stream
+                .write_all(b"#1 2\nMESSAGE alerts hi\n#0 1\n")
+                .unwrap();
+        });
+
+        let mut connection = Connection::connect("127.0.0.1", port, None).unwrap();
+        let requests = vec!["GET a\n".to_string(), "GET b".to_string()];
+        assert_eq!(
+            connection.send_pipelined(&requests).unwrap(),
+            vec!["1\n".to_string(), "2\n".to_string()]
+        );
+
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn connects_over_tls_with_client_certificates() {
+        let write_pem = |name: &str, pem: String| {
+            let path = std::path::PathBuf::from(format!("{}.test.pem", name));
+            std::fs::write(&path, pem).unwrap();
+            path
+        };
+        let server = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let client = rcgen::generate_simple_self_signed(vec!["repl".to_string()]).unwrap();
+        let server_cert = write_pem("server.cert", server.cert.pem());
+        let server_key = write_pem("server.key", server.key_pair.serialize_pem());
+        let client_cert = write_pem("client.cert", client.cert.pem());
+        let client_key = write_pem("client.key", client.key_pair.serialize_pem());
+
+        let server_config = tls::server_config(&tls::ServerFiles {
+            cert: server_cert.clone(),
+            key: server_key,
+            client_ca: Some(client_cert.clone()),
+        })
+        .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().unwrap();
+                let connection = rustls::ServerConnection::new(Arc::clone(&server_config)).unwrap();
+                let mut reader = BufReader::new(StreamOwned::new(connection, stream));
+                let mut request = String::new();
+                if reader.read_line(&mut request).is_ok() {
+                    reader.get_mut().write_all(request.as_bytes()).unwrap();
+                }
+            }
+        });
+
+        let anonymous = tls::client_config(&tls::ClientFiles {
+            ca: server_cert.clone(),
+            identity: None,
+        })
+        .unwrap();
+        let mut connection = Connection::connect("localhost", port, Some(anonymous)).unwrap();
+        // The server rejects the handshake, so the connection fails or closes unanswered.
+        let response = connection.send_request("PING\n").unwrap_or_default();
+        assert_eq!(response, "");
+
+        let identified = tls::client_config(&tls::ClientFiles {
+            ca: server_cert,
+            identity: Some((client_cert, client_key)),
+        })
+        .unwrap();
+        let mut connection = Connection::connect("localhost", port, Some(identified)).unwrap();
+        assert_eq!(connection.send_request("PING\n").unwrap(), "PING\n");
+
+        server.join().unwrap();
+    }
 }
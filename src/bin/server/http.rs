@@ -0,0 +1,487 @@
+use std::sync::{Arc, RwLock};
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use myco_kv::{
+    acl::AccessControl,
+    errors::TransactionError,
+    kvmap::KVMap,
+    operation::{reply::Reply, timeunit, value::Value, Operation},
+    session::{Protocol, Session},
+};
+use serde::Deserialize;
+use tokio::{net::TcpListener, sync::Semaphore};
+
+use super::ws;
+
+#[derive(Clone)]
+pub(super) struct Gateway {
+    pub(super) kvmap: Arc<RwLock<KVMap>>,
+    pub(super) access: Arc<AccessControl>,
+    pub(super) workers: Arc<Semaphore>,
+}
+
+#[derive(Deserialize)]
+struct KeyOptions {
+    depth: Option<usize>,
+    ttl: Option<String>,
+}
+
+pub async fn listen(
+    port: u16,
+    kvmap: Arc<RwLock<KVMap>>,
+    access: Arc<AccessControl>,
+    workers: Arc<Semaphore>,
+) {
+    let addr = format!("0.0.0.0:{}", port);
+
+    let listener = TcpListener::bind(&addr).await.unwrap();
+
+    println!("HTTP server listening on port: {}", port);
+
+    if let Err(e) = axum::serve(listener, router(kvmap, access, workers)).await {
+        eprintln!("HTTP server stopped: {}", e);
+    }
+}
+
+/// Routes `/kv/a/b` to the key `a.b`, and `/kv/a/*` to the subtree under `a`. Live
+/// subscriptions are served over a WebSocket at `/ws`. Once users exist, requests must
+/// carry their credentials with HTTP Basic authentication.
+fn router(
+    kvmap: Arc<RwLock<KVMap>>,
+    access: Arc<AccessControl>,
+    workers: Arc<Semaphore>,
+) -> Router {
+    Router::new()
+        .route("/kv/{*path}", get(get_key).put(put_key).delete(delete_key))
+        .route("/ws", get(ws::upgrade))
+        .with_state(Gateway {
+            kvmap,
+            access,
+            workers,
+        })
+}
+
+async fn get_key(
+    State(gateway): State<Gateway>,
+    Path(path): Path<String>,
+    Query(options): Query<KeyOptions>,
+    headers: HeaderMap,
+) -> Response {
+    let operation = match parse_key(&path, options.depth) {
+        Ok(key) => Operation::Get(key),
+        Err(e) => return error_response(e),
+    };
+
+    match gateway.process(&headers, vec![operation]).await {
+        Ok(mut results) => {
+            let (_, value) = results.remove(0).to_json();
+            Json(value).into_response()
+        }
+        Err(e) => error_response(e),
+    }
+}
+
+/// Writes a JSON scalar to the key, or each value in a JSON object to the keys under it.
+async fn put_key(
+    State(gateway): State<Gateway>,
+    Path(path): Path<String>,
+    Query(options): Query<KeyOptions>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let operation = match put_operation(&path, &options, &body) {
+        Ok(operation) => operation,
+        Err(e) => return error_response(e),
+    };
+
+    match gateway
+        .process(&headers, operation.into_iter().collect())
+        .await
+    {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn delete_key(
+    State(gateway): State<Gateway>,
+    Path(path): Path<String>,
+    Query(options): Query<KeyOptions>,
+    headers: HeaderMap,
+) -> Response {
+    let operation = match parse_key(&path, options.depth) {
+        Ok(key) => Operation::Delete(key),
+        Err(e) => return error_response(e),
+    };
+
+    match gateway.process(&headers, vec![operation]).await {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+impl Gateway {
+    /// Starts a session for the user whose credentials are in the request's headers.
+    pub(super) async fn authenticate(
+        &self,
+        headers: &HeaderMap,
+    ) -> Result<Session, TransactionError> {
+        let credentials = credentials(headers)?;
+        let mut session = Session::with_access(Arc::clone(&self.access));
+        let (user, password) = match credentials {
+            Some(credentials) => credentials,
+            None => return Ok(session),
+        };
+
+        // Verifying a password is deliberately slow, so it runs on a worker.
+        let permit = self.workers.clone().acquire_owned().await.unwrap();
+        let kvmap = Arc::clone(&self.kvmap);
+        let task = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            session.process(&kvmap, Operation::Auth(user, password))?;
+            Ok(session)
+        });
+
+        task.await.map_err(|_| TransactionError::InternalError)?
+    }
+
+    /// Processes the operations in order on a worker, stopping at the first that fails.
+    async fn process(
+        &self,
+        headers: &HeaderMap,
+        operations: Vec<Operation>,
+    ) -> Result<Vec<Reply>, TransactionError> {
+        let mut session = self.authenticate(headers).await?;
+        let permit = self.workers.clone().acquire_owned().await.unwrap();
+        let kvmap = Arc::clone(&self.kvmap);
+        let task = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            operations
+                .into_iter()
+                .map(|operation| session.process(&kvmap, operation))
+                .collect()
+        });
+
+        task.await.map_err(|_| TransactionError::InternalError)?
+    }
+}
+
+/// Reads the user and password from a Basic `Authorization` header, if there is one.
+fn credentials(headers: &HeaderMap) -> Result<Option<(String, String)>, TransactionError> {
+    let header = match headers.get(header::AUTHORIZATION) {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+
+    header
+        .to_str()
+        .ok()
+        .and_then(|header| header.strip_prefix("Basic "))
+        .and_then(|encoded| STANDARD.decode(encoded.trim()).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .and_then(|decoded| {
+            let (user, password) = decoded.split_once(':')?;
+            Some(Some((user.to_string(), password.to_string())))
+        })
+        .ok_or(TransactionError::AuthenticationFailed)
+}
+
+/// Turns the body into the write it describes. Several values, or a value with a `ttl`,
+/// are written as one batch, so that either all of them are written or none are. An empty
+/// object writes nothing.
+fn put_operation(
+    path: &str,
+    options: &KeyOptions,
+    body: &[u8],
+) -> Result<Option<Operation>, TransactionError> {
+    if options.depth.is_some() {
+        return Err(TransactionError::InvalidValue("depth".to_string()));
+    }
+    let key = parse_key(path, None)?;
+    let body: serde_json::Value = serde_json::from_slice(body)
+        .map_err(|_| TransactionError::InvalidValue("request body".to_string()))?;
+
+    let mut values = Vec::new();
+    flatten(key, body, &mut values)?;
+
+    let mut operations = Vec::new();
+    if let Some(ttl) = &options.ttl {
+        let duration = timeunit::parse_duration(ttl)
+            .ok_or(TransactionError::InvalidValue("ttl".to_string()))?;
+        for (key, _) in &values {
+            operations.push(Operation::Expire(key.to_string(), duration));
+        }
+    }
+
+    let puts = values
+        .into_iter()
+        .map(|(key, value)| Operation::Put(key, value));
+    let mut operations: Vec<Operation> = puts.chain(operations).collect();
+    match operations.len() {
+        0 | 1 => Ok(operations.pop()),
+        _ => Ok(Some(Operation::Batch(operations))),
+    }
+}
+
+/// Collects the values in a JSON object under the key, treating `_` as the key's own value
+/// as subtree results do.
+fn flatten(
+    key: String,
+    json: serde_json::Value,
+    values: &mut Vec<(String, Value)>,
+) -> Result<(), TransactionError> {
+    let value = match json {
+        serde_json::Value::Object(children) => {
+            for (child, json) in children {
+                match child.as_str() {
+                    "_" => flatten(key.clone(), json, values)?,
+                    _ => flatten(format!("{}.{}", key, parse_segment(&child)?), json, values)?,
+                }
+            }
+            return Ok(());
+        }
+        serde_json::Value::Array(_) => {
+            return Err(TransactionError::InvalidValue("array".to_string()));
+        }
+        serde_json::Value::String(string) if string.contains(['\r', '\n']) => {
+            return Err(TransactionError::InvalidValue(string));
+        }
+        serde_json::Value::String(string) => Value::String(string),
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(number) => Value::Integer(number),
+            None => Value::Float(number.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_json::Value::Bool(boolean) => Value::Boolean(boolean),
+        serde_json::Value::Null => Value::Null,
+    };
+
+    values.push((key, value));
+    Ok(())
+}
+
+/// Turns a URL path into a key. A trailing `/*` selects the subtree, limited to `depth`
+/// levels if given.
+fn parse_key(path: &str, depth: Option<usize>) -> Result<String, TransactionError> {
+    let (path, subtree) = match path.strip_suffix('*') {
+        Some(path) if path.is_empty() || path.ends_with('/') => (path.trim_end_matches('/'), true),
+        _ => (path, false),
+    };
+
+    let mut segments = Vec::new();
+    if !path.is_empty() {
+        for segment in path.split('/') {
+            segments.push(parse_segment(segment)?.to_string());
+        }
+    }
+    match (subtree, depth) {
+        (true, Some(depth)) => segments.push(format!("*{}", depth)),
+        (true, None) => segments.push("*".to_string()),
+        (false, Some(_)) => return Err(TransactionError::InvalidValue("depth".to_string())),
+        (false, None) => {}
+    }
+
+    match segments.is_empty() {
+        true => Err(TransactionError::MissingKey),
+        false => Ok(segments.join(".")),
+    }
+}
+
+fn parse_segment(segment: &str) -> Result<&str, TransactionError> {
+    match segment.is_empty() || segment.contains(|c: char| c == '.' || c.is_whitespace()) {
+        true => Err(TransactionError::InvalidKey(segment.to_string())),
+        false => Ok(segment),
+    }
+}
+
+pub(super) fn error_response(error: TransactionError) -> Response {
+    let status = match error {
+        TransactionError::AuthenticationRequired | TransactionError::AuthenticationFailed => {
+            let body = Protocol::Typed.format(Err(error));
+            let headers = [
+                (header::CONTENT_TYPE, "application/json"),
+                (header::WWW_AUTHENTICATE, r#"Basic realm="MycoKV""#),
+            ];
+            return (StatusCode::UNAUTHORIZED, headers, body).into_response();
+        }
+        TransactionError::PermissionDenied(_) => StatusCode::FORBIDDEN,
+        TransactionError::KeyNotFound(_) => StatusCode::NOT_FOUND,
+        TransactionError::InternalError
+        | TransactionError::SerializationFailure
+        | TransactionError::LogWriteFail(_)
+        | TransactionError::LogReadFail(_)
+        | TransactionError::LogLoadFail
+        | TransactionError::RestoreError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        _ => StatusCode::BAD_REQUEST,
+    };
+    let body = Protocol::Typed.format(Err(error));
+
+    (status, [("content-type", "application/json")], body).into_response()
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use axum::{body::Body, http::Request};
+    use myco_kv::{clock::ManualClock, wal::WriteAheadLog};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn send(router: &Router, method: &str, uri: &str, body: &str) -> (StatusCode, String) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn maps_requests_to_keys() {
+        let wal = Arc::new(Mutex::new(
+            WriteAheadLog::new("log.server.http.test.txt").unwrap(),
+        ));
+        wal.lock().unwrap().clear().unwrap();
+        let clock = Arc::new(ManualClock::new(0));
+        let kvmap = Arc::new(RwLock::new(KVMap::with_clock(Arc::clone(&wal), clock)));
+        let router = router(
+            Arc::clone(&kvmap),
+            Arc::new(AccessControl::new()),
+            Arc::new(Semaphore::new(1)),
+        );
+
+        let no_content = (StatusCode::NO_CONTENT, String::new());
+        assert_eq!(
+            send(&router, "PUT", "/kv/users/42", r#""Ada""#).await,
+            no_content
+        );
+        assert_eq!(
+            send(
+                &router,
+                "PUT",
+                "/kv/users/42?ttl=30s",
+                r#"{"_":"Ada Lovelace","age":36,"address":{"city":"London"}}"#,
+            )
+            .await,
+            no_content
+        );
+
+        assert_eq!(
+            send(&router, "GET", "/kv/users/42", "").await,
+            (StatusCode::OK, r#""Ada Lovelace""#.to_string())
+        );
+        assert_eq!(
+            send(&router, "GET", "/kv/users/*?depth=2", "").await,
+            (
+                StatusCode::OK,
+                r#"{"42":{"_":"Ada Lovelace","age":36}}"#.to_string()
+            )
+        );
+        assert_eq!(kvmap.read().unwrap().ttl("users.42.address.city"), 30000);
+        assert_eq!(wal.lock().unwrap().read_all_lines().unwrap().count(), 2);
+
+        assert_eq!(
+            send(&router, "PUT", "/kv/users/44", r#"{"name":"Bob","*x":1}"#).await,
+            (
+                StatusCode::BAD_REQUEST,
+                r#"{"ok":false,"code":"E03","error":"Invalid key \"users.44.*x\""}"#.to_string()
+            )
+        );
+        assert_eq!(
+            send(&router, "GET", "/kv/users/44/name", "").await.0,
+            StatusCode::NOT_FOUND
+        );
+
+        let path = r#""C:\\temp""#;
+        assert_eq!(
+            send(&router, "PUT", "/kv/users/42/home", path).await,
+            no_content
+        );
+        assert_eq!(
+            send(&router, "GET", "/kv/users/42/home", "").await,
+            (StatusCode::OK, path.to_string())
+        );
+
+        assert_eq!(
+            send(&router, "DELETE", "/kv/users/42/age", "").await,
+            no_content
+        );
+        assert_eq!(
+            send(&router, "GET", "/kv/users/42/age", "").await,
+            (
+                StatusCode::NOT_FOUND,
+                r#"{"ok":false,"code":"E09","error":"Key users.42.age not found"}"#.to_string()
+            )
+        );
+        assert_eq!(
+            send(&router, "PUT", "/kv/users/43", "[1, 2]").await,
+            (
+                StatusCode::BAD_REQUEST,
+                r#"{"ok":false,"code":"E05","error":"Invalid value array"}"#.to_string()
+            )
+        );
+        assert_eq!(
+            send(&router, "GET", "/kv/users.43", "").await.0,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn requires_credentials_once_users_exist() {
+        let wal = Arc::new(Mutex::new(
+            WriteAheadLog::new("log.server.http.auth.test.txt").unwrap(),
+        ));
+        wal.lock().unwrap().clear().unwrap();
+        let kvmap = Arc::new(RwLock::new(KVMap::new(wal)));
+        let access = Arc::new(AccessControl::new());
+        for command in [
+            "SETUSER root secret",
+            "SETUSER ada pass",
+            "GRANT ada users.* read",
+        ] {
+            let command = myco_kv::acl::AclCommand::parse(command.split_whitespace()).unwrap();
+            access.execute(command).unwrap();
+        }
+        let router = router(kvmap, access, Arc::new(Semaphore::new(1)));
+
+        let send_as = |user: &str, method: &str| {
+            let credentials = STANDARD.encode(user);
+            let request = Request::builder()
+                .method(method)
+                .uri("/kv/users/42")
+                .header(header::AUTHORIZATION, format!("Basic {}", credentials))
+                .body(Body::from("1"))
+                .unwrap();
+            let router = router.clone();
+            async move { router.oneshot(request).await.unwrap().status() }
+        };
+
+        let response = router
+            .clone()
+            .oneshot(Request::get("/kv/users/42").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().contains_key(header::WWW_AUTHENTICATE));
+
+        assert_eq!(send_as("ada:wrong", "GET").await, StatusCode::UNAUTHORIZED);
+        assert_eq!(send_as("ada:pass", "PUT").await, StatusCode::FORBIDDEN);
+        assert_eq!(send_as("root:secret", "PUT").await, StatusCode::NO_CONTENT);
+        assert_eq!(send_as("ada:pass", "GET").await, StatusCode::OK);
+    }
+}
@@ -1,63 +1,391 @@
-use std::{
-    io::{prelude::*, BufReader},
-    net::{TcpListener, TcpStream},
-    sync::{Arc, Mutex},
-    thread,
+use std::sync::{Arc, RwLock};
+
+use myco_kv::{
+    acl::AccessControl, events::KeyEvent, kvmap::KVMap, pubsub::Message, session::Session,
+};
+use rustls::ServerConfig;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter},
+    net::TcpListener,
+    runtime::Runtime,
+    sync::{
+        broadcast::{self, error::RecvError},
+        Semaphore,
+    },
 };
+use tokio_rustls::TlsAcceptor;
 
-use myco_kv::{kvmap::KVMap, operation::Operation};
+mod http;
+mod resp;
+mod unix;
+mod ws;
 
-pub fn start(port: u16, kvmap: Arc<Mutex<KVMap>>) {
-    let addr = format!("0.0.0.0:{}", port);
+pub use unix::SocketFile;
 
-    let listener = TcpListener::bind(&addr).unwrap();
+/// The optional listeners started alongside the main one, and the TLS configuration of
+/// the main one if it only accepts encrypted connections.
+pub struct Listeners {
+    pub resp_port: Option<u16>,
+    pub http_port: Option<u16>,
+    pub socket: Option<SocketFile>,
+    pub tls: Option<Arc<ServerConfig>>,
+}
 
-    println!("Server listening on port: {}", port);
+/// Starts the listeners, with the main one on `port`. Without a port, the server only
+/// listens on its Unix socket.
+pub fn start(
+    port: Option<u16>,
+    listeners: Listeners,
+    kvmap: Arc<RwLock<KVMap>>,
+    access: Arc<AccessControl>,
+    workers: usize,
+) {
+    let runtime = Runtime::new().expect("Could not start the server runtime.");
 
-    let mut instances = Vec::new();
+    // Commands run on blocking threads, at most `workers` at a time across both
+    // protocols, so that idle connections cost nothing but their socket.
+    let workers = Arc::new(Semaphore::new(workers));
 
-    for stream in listener.incoming() {
-        let stream = stream.unwrap();
-        let kvmap_instance = Arc::clone(&kvmap);
+    if let Some(resp_port) = listeners.resp_port {
+        runtime.spawn(resp::listen(
+            resp_port,
+            Arc::clone(&kvmap),
+            Arc::clone(&access),
+            Arc::clone(&workers),
+        ));
+    }
+    if let Some(http_port) = listeners.http_port {
+        runtime.spawn(http::listen(
+            http_port,
+            Arc::clone(&kvmap),
+            Arc::clone(&access),
+            Arc::clone(&workers),
+        ));
+    }
+    let socket = listeners.socket.map(|socket| {
+        unix::listen(
+            socket,
+            Arc::clone(&kvmap),
+            Arc::clone(&access),
+            Arc::clone(&workers),
+        )
+    });
+    let tls = listeners.tls.map(TlsAcceptor::from);
+    match (port, socket) {
+        (Some(port), socket) => {
+            if let Some(socket) = socket {
+                runtime.spawn(socket);
+            }
+            runtime.block_on(listen(port, tls, kvmap, access, workers));
+        }
+        (None, Some(socket)) => runtime.block_on(socket),
+        (None, None) => {}
+    }
+}
+
+async fn listen(
+    port: u16,
+    tls: Option<TlsAcceptor>,
+    kvmap: Arc<RwLock<KVMap>>,
+    access: Arc<AccessControl>,
+    workers: Arc<Semaphore>,
+) {
+    let addr = format!("0.0.0.0:{}", port);
+
+    let listener = TcpListener::bind(&addr).await.unwrap();
+
+    match tls {
+        Some(_) => println!("Server listening with TLS on port: {}", port),
+        None => println!("Server listening on port: {}", port),
+    }
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                eprintln!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
 
-        instances.push(thread::spawn(move || {
-            handle_connection(stream, kvmap_instance)
-        }));
+        let kvmap = Arc::clone(&kvmap);
+        let access = Arc::clone(&access);
+        let workers = Arc::clone(&workers);
+        let tls = tls.clone();
+        tokio::spawn(async move {
+            match tls {
+                Some(tls) => match tls.accept(stream).await {
+                    Ok(stream) => handle_connection(stream, kvmap, access, workers).await,
+                    Err(e) => eprintln!("Failed to establish TLS connection: {}", e),
+                },
+                None => handle_connection(stream, kvmap, access, workers).await,
+            }
+        });
     }
 }
 
-fn handle_connection(mut stream: TcpStream, kvmap: Arc<Mutex<KVMap>>) {
+/// Answers each line received on the stream with a single line, until the client
+/// disconnects. Messages for the session's subscriptions are written as they arrive.
+///
+/// Requests are answered in order, and responses are only flushed once every request
+/// received so far has been answered, so pipelined requests share writes.
+pub async fn handle_connection<S>(
+    stream: S,
+    kvmap: Arc<RwLock<KVMap>>,
+    access: Arc<AccessControl>,
+    workers: Arc<Semaphore>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (reader, writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    let mut writer = BufWriter::new(writer);
+    let mut session = Session::with_access(access);
+    let mut subscription: Option<Subscription> = None;
+
     loop {
-        let mut buf_reader = BufReader::new(&mut stream);
-
-        let mut request = String::new();
-        match buf_reader.read_line(&mut request) {
-            Ok(_) => {
-                let operation = Operation::parse(request);
-
-                let response = match operation {
-                    Ok(operation) => {
-                        let mut kvmap = kvmap.lock().unwrap();
-                        let result = kvmap.process_operation(operation);
-                        match result {
-                            Ok(result) => result,
-                            Err(e) => e.message(),
-                        }
+        let responses = tokio::select! {
+            request = lines.next_line() => {
+                let request = match request {
+                    Ok(Some(request)) => request,
+                    Ok(None) => break,
+                    // TLS clients often disconnect without saying goodbye first.
+                    Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                    Err(e) => {
+                        eprintln!("Failed to read from connection: {}", e);
+                        break;
                     }
-
-                    Err(e) => e.message(),
                 };
 
-                let response = response + "\n";
-                if let Err(e) = stream.write_all(response.as_bytes()) {
-                    eprintln!("Failed to send response: {}", e);
-                    break;
+                let permit = workers.clone().acquire_owned().await.unwrap();
+                let kvmap = Arc::clone(&kvmap);
+                let subscribed = subscription.is_some();
+                let task = tokio::task::spawn_blocking(move || {
+                    let _permit = permit;
+                    let response = session.execute(&kvmap, request);
+                    // Start listening as soon as the command that subscribed completes.
+                    let subscription = (session.is_subscribed() && !subscribed)
+                        .then(|| Subscription::new(&kvmap.read().unwrap()));
+                    (session, response, subscription)
+                });
+                let (response, new_subscription);
+                (session, response, new_subscription) =
+                    task.await.expect("Command processing panicked.");
+
+                if !session.is_subscribed() {
+                    subscription = None;
+                } else if new_subscription.is_some() {
+                    subscription = new_subscription;
                 }
+                vec![response]
             }
-            Err(e) => {
-                eprintln!("Failed to read from connection: {}", e);
-                break;
+            message = next_message(&mut subscription) => session.deliver(&message),
+        };
+
+        for response in responses {
+            let response = response + "\n";
+            if let Err(e) = writer.write_all(response.as_bytes()).await {
+                eprintln!("Failed to send response: {}", e);
+                return;
             }
         }
+        if lines.get_ref().buffer().is_empty() {
+            if let Err(e) = writer.flush().await {
+                eprintln!("Failed to send response: {}", e);
+                return;
+            }
+        }
+    }
+
+    writer.flush().await.unwrap_or(());
+}
+
+/// Receives published messages and key changes while a session has subscriptions.
+struct Subscription {
+    events: broadcast::Receiver<KeyEvent>,
+    messages: broadcast::Receiver<Message>,
+}
+
+impl Subscription {
+    fn new(kvmap: &KVMap) -> Self {
+        Subscription {
+            events: kvmap.subscribe(),
+            messages: kvmap.subscribe_messages(),
+        }
+    }
+
+    /// Waits for the next message, with key changes published on their keyspace channel.
+    async fn recv(&mut self) -> Message {
+        loop {
+            let result = tokio::select! {
+                event = self.events.recv() => event.map(|event| Message::from_event(&event)),
+                message = self.messages.recv() => message,
+            };
+            match result {
+                Ok(message) => return message,
+                Err(RecvError::Lagged(missed)) => {
+                    eprintln!("Subscriber fell behind and missed {} messages", missed);
+                }
+                Err(RecvError::Closed) => return std::future::pending().await,
+            }
+        }
+    }
+}
+
+async fn next_message(subscription: &mut Option<Subscription>) -> Message {
+    match subscription {
+        Some(subscription) => subscription.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use myco_kv::wal::WriteAheadLog;
+    use tokio::io::{duplex, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn answers_each_line() {
+        let wal = Arc::new(Mutex::new(
+            WriteAheadLog::new("log.server.test.txt").unwrap(),
+        ));
+        wal.lock().unwrap().clear().unwrap();
+        let kvmap = Arc::new(RwLock::new(KVMap::new(wal)));
+
+        let (client, server) = duplex(1024);
+        let connection = tokio::spawn(handle_connection(
+            server,
+            kvmap,
+            Arc::new(AccessControl::new()),
+            Arc::new(Semaphore::new(1)),
+        ));
+
+        let (reader, mut writer) = tokio::io::split(client);
+        let mut lines = BufReader::new(reader).lines();
+        writer
+            .write_all(b"PUT a.b 1\nGET a.*\nNOPE\n")
+            .await
+            .unwrap();
+
+        assert_eq!(lines.next_line().await.unwrap(), Some("1".to_string()));
+        assert_eq!(
+            lines.next_line().await.unwrap(),
+            Some(r#"{"b":1}"#.to_string())
+        );
+        assert_eq!(
+            lines.next_line().await.unwrap(),
+            Some("E01: Command NOPE not recognized".to_string())
+        );
+
+        drop((lines, writer));
+        connection.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn delivers_published_messages() {
+        let wal = Arc::new(Mutex::new(
+            WriteAheadLog::new("log.server.pubsub.test.txt").unwrap(),
+        ));
+        wal.lock().unwrap().clear().unwrap();
+        let kvmap = Arc::new(RwLock::new(KVMap::new(wal)));
+        let workers = Arc::new(Semaphore::new(2));
+
+        let (subscriber, server) = duplex(1024);
+        tokio::spawn(handle_connection(
+            server,
+            Arc::clone(&kvmap),
+            Arc::new(AccessControl::new()),
+            Arc::clone(&workers),
+        ));
+        let (reader, mut subscriber_writer) = tokio::io::split(subscriber);
+        let mut subscriber_lines = BufReader::new(reader).lines();
+
+        let (publisher, server) = duplex(1024);
+        tokio::spawn(handle_connection(
+            server,
+            kvmap,
+            Arc::new(AccessControl::new()),
+            workers,
+        ));
+        let (reader, mut publisher_writer) = tokio::io::split(publisher);
+        let mut publisher_lines = BufReader::new(reader).lines();
+
+        subscriber_writer
+            .write_all(b"SUBSCRIBE alerts\nPSUBSCRIBE __keyspace__.config.*\n")
+            .await
+            .unwrap();
+        assert_eq!(subscriber_lines.next_line().await.unwrap().unwrap(), "1");
+        assert_eq!(subscriber_lines.next_line().await.unwrap().unwrap(), "2");
+
+        publisher_writer
+            .write_all(b"PUBLISH alerts disk is full\nPUT config.theme 1\nPUT other 1\n")
+            .await
+            .unwrap();
+        for expected in ["1", "1", "1"] {
+            assert_eq!(
+                publisher_lines.next_line().await.unwrap().unwrap(),
+                expected
+            );
+        }
+
+        // Messages and key changes are delivered separately, so may arrive in either order.
+        let mut messages = vec![
+            subscriber_lines.next_line().await.unwrap().unwrap(),
+            subscriber_lines.next_line().await.unwrap().unwrap(),
+        ];
+        messages.sort();
+        assert_eq!(
+            messages,
+            [
+                "MESSAGE alerts disk is full",
+                "PMESSAGE __keyspace__.config.* __keyspace__.config.theme put",
+            ]
+        );
+
+        subscriber_writer
+            .write_all(b"UNSUBSCRIBE\nPUNSUBSCRIBE\nGET other\n")
+            .await
+            .unwrap();
+        assert_eq!(subscriber_lines.next_line().await.unwrap().unwrap(), "1");
+        assert_eq!(subscriber_lines.next_line().await.unwrap().unwrap(), "0");
+        assert_eq!(subscriber_lines.next_line().await.unwrap().unwrap(), "1");
+    }
+
+    #[tokio::test]
+    async fn answers_pipelined_requests_in_order() {
+        let wal = Arc::new(Mutex::new(
+            WriteAheadLog::new("log.server.pipeline.test.txt").unwrap(),
+        ));
+        wal.lock().unwrap().clear().unwrap();
+        let kvmap = Arc::new(RwLock::new(KVMap::new(wal)));
+
+        let (mut client, server) = duplex(64 * 1024);
+        let connection = tokio::spawn(handle_connection(
+            server,
+            kvmap,
+            Arc::new(AccessControl::new()),
+            Arc::new(Semaphore::new(4)),
+        ));
+
+        let requests: String = (0..100)
+            .map(|i| format!("#{} PUT batch.k{} {}\n", i, i, i))
+            .collect();
+        client.write_all(requests.as_bytes()).await.unwrap();
+        client.write_all(b"COUNT batch.*\n").await.unwrap();
+        client.shutdown().await.unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        let expected: String = (0..100)
+            .map(|i| format!("#{} {}\n", i, i))
+            .chain(std::iter::once("100\n".to_string()))
+            .collect();
+        assert_eq!(response, expected);
+
+        connection.await.unwrap();
     }
 }
@@ -0,0 +1,144 @@
+use std::sync::{Arc, RwLock};
+
+use myco_kv::{
+    acl::AccessControl,
+    kvmap::KVMap,
+    resp::{self, RespConnection},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter},
+    net::TcpListener,
+    sync::Semaphore,
+};
+
+pub async fn listen(
+    port: u16,
+    kvmap: Arc<RwLock<KVMap>>,
+    access: Arc<AccessControl>,
+    workers: Arc<Semaphore>,
+) {
+    let addr = format!("0.0.0.0:{}", port);
+
+    let listener = TcpListener::bind(&addr).await.unwrap();
+
+    println!("RESP server listening on port: {}", port);
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                eprintln!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+
+        tokio::spawn(handle_connection(
+            stream,
+            Arc::clone(&kvmap),
+            Arc::clone(&access),
+            Arc::clone(&workers),
+        ));
+    }
+}
+
+/// Answers each Redis command received on the stream with a single reply, until the
+/// client disconnects. Replies are flushed once every command received so far has been
+/// answered, so pipelined commands share writes.
+pub async fn handle_connection<S>(
+    stream: S,
+    kvmap: Arc<RwLock<KVMap>>,
+    access: Arc<AccessControl>,
+    workers: Arc<Semaphore>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (reader, writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+    let mut writer = BufWriter::new(writer);
+    let mut connection = RespConnection::with_access(access);
+
+    loop {
+        let command = match resp::read_command(&mut reader).await {
+            Ok(Some(command)) => command,
+            Ok(None) => break,
+            Err(e) => {
+                let reply = format!("-ERR Protocol error: {}\r\n", e);
+                writer.write_all(reply.as_bytes()).await.unwrap_or(());
+                break;
+            }
+        };
+
+        let permit = workers.clone().acquire_owned().await.unwrap();
+        let kvmap = Arc::clone(&kvmap);
+        let task = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let reply = connection.execute(&kvmap, command);
+            (connection, reply)
+        });
+        let reply;
+        (connection, reply) = task.await.expect("Command processing panicked.");
+
+        let mut response = Vec::new();
+        reply.encode(connection.version(), &mut response);
+        if let Err(e) = writer.write_all(&response).await {
+            eprintln!("Failed to send response: {}", e);
+            return;
+        }
+        if reader.buffer().is_empty() {
+            if let Err(e) = writer.flush().await {
+                eprintln!("Failed to send response: {}", e);
+                return;
+            }
+        }
+    }
+
+    writer.flush().await.unwrap_or(());
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use myco_kv::wal::WriteAheadLog;
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn answers_each_command() {
+        let wal = Arc::new(Mutex::new(
+            WriteAheadLog::new("log.server.resp.test.txt").unwrap(),
+        ));
+        wal.lock().unwrap().clear().unwrap();
+        let kvmap = Arc::new(RwLock::new(KVMap::new(wal)));
+
+        let (mut client, server) = duplex(1024);
+        let connection = tokio::spawn(handle_connection(
+            server,
+            kvmap,
+            Arc::new(AccessControl::new()),
+            Arc::new(Semaphore::new(1)),
+        ));
+
+        client
+            .write_all(b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$2\r\nhi\r\nGET a\r\nHELLO 3\r\nGET b\r\n")
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert_eq!(
+            response,
+            concat!(
+                "+OK\r\n",
+                "$2\r\nhi\r\n",
+                "%4\r\n$6\r\nserver\r\n$6\r\nmycokv\r\n$7\r\nversion\r\n$5\r\n0.1.0\r\n",
+                "$5\r\nproto\r\n:3\r\n$4\r\nmode\r\n$10\r\nstandalone\r\n",
+                "_\r\n",
+            )
+        );
+
+        connection.await.unwrap();
+    }
+}
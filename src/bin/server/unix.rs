@@ -0,0 +1,171 @@
+use std::{
+    ffi::OsString,
+    fs, io,
+    os::unix::{
+        fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+        net::UnixStream as StdUnixStream,
+    },
+    path::{Path, PathBuf},
+    process,
+    sync::{Arc, RwLock},
+};
+
+use myco_kv::{acl::AccessControl, kvmap::KVMap};
+use tokio::{net::UnixListener, sync::Semaphore};
+
+use super::handle_connection;
+
+/// A Unix socket to listen on, and the permissions it is created with. Only users who can
+/// write to the socket file can connect.
+pub struct SocketFile {
+    pub path: PathBuf,
+    pub mode: u32,
+}
+
+pub async fn listen(
+    socket: SocketFile,
+    kvmap: Arc<RwLock<KVMap>>,
+    access: Arc<AccessControl>,
+    workers: Arc<Semaphore>,
+) {
+    let listener = bind(&socket).unwrap();
+
+    println!("Server listening on socket: {}", socket.path.display());
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                eprintln!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+
+        tokio::spawn(handle_connection(
+            stream,
+            Arc::clone(&kvmap),
+            Arc::clone(&access),
+            Arc::clone(&workers),
+        ));
+    }
+}
+
+/// Creates the socket, replacing one left behind by a previous run. A socket that a
+/// running server still answers on, or any other kind of file at the path, is left alone.
+fn bind(socket: &SocketFile) -> io::Result<UnixListener> {
+    match fs::symlink_metadata(&socket.path) {
+        Ok(metadata) if metadata.file_type().is_socket() => remove_stale(&socket.path)?,
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", socket.path.display()),
+            ))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    // The socket is created in a directory only this process can enter, and only linked
+    // into place once it has its permissions, so nobody can connect to it before then.
+    let file_name = socket.path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a file path", socket.path.display()),
+        )
+    })?;
+    let mut private_name = OsString::from(".");
+    private_name.push(file_name);
+    private_name.push(format!(".{}", process::id()));
+    let private = socket.path.with_file_name(private_name);
+    fs::DirBuilder::new().mode(0o700).create(&private)?;
+
+    let result = bind_within(socket, &private);
+    fs::remove_dir_all(&private).unwrap_or(());
+    result
+}
+
+fn bind_within(socket: &SocketFile, directory: &Path) -> io::Result<UnixListener> {
+    let temporary = directory.join("socket");
+    let listener = UnixListener::bind(&temporary)?;
+    fs::set_permissions(&temporary, fs::Permissions::from_mode(socket.mode))?;
+    fs::hard_link(&temporary, &socket.path)?;
+    Ok(listener)
+}
+
+/// Removes a socket unless a server is still listening on it.
+fn remove_stale(path: &Path) -> io::Result<()> {
+    match StdUnixStream::connect(path) {
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("a server is already listening on {}", path.display()),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => fs::remove_file(path),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use myco_kv::wal::WriteAheadLog;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::UnixStream,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn replaces_only_stale_sockets_and_sets_permissions() {
+        let wal = Arc::new(Mutex::new(
+            WriteAheadLog::new("log.server.unix.test.txt").unwrap(),
+        ));
+        wal.lock().unwrap().clear().unwrap();
+        let kvmap = Arc::new(RwLock::new(KVMap::new(wal)));
+        let socket = SocketFile {
+            path: PathBuf::from("server.test.sock"),
+            mode: 0o600,
+        };
+
+        drop(bind(&socket).unwrap());
+        let listener = bind(&socket).unwrap();
+        let mode = fs::metadata(&socket.path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let access = Arc::new(AccessControl::new());
+            handle_connection(stream, kvmap, access, Arc::new(Semaphore::new(1))).await;
+        });
+
+        let (reader, mut writer) = UnixStream::connect(&socket.path)
+            .await
+            .unwrap()
+            .into_split();
+        let mut lines = BufReader::new(reader).lines();
+        writer.write_all(b"PUT a 1\n").await.unwrap();
+        assert_eq!(lines.next_line().await.unwrap(), Some("1".to_string()));
+
+        drop((lines, writer));
+        server.await.unwrap();
+        fs::remove_file(&socket.path).unwrap();
+
+        let live = SocketFile {
+            path: PathBuf::from("server.live.test.sock"),
+            mode: 0o600,
+        };
+        let _listener = bind(&live).unwrap();
+        assert_eq!(bind(&live).unwrap_err().kind(), io::ErrorKind::AddrInUse);
+        let metadata = fs::symlink_metadata(&live.path).unwrap();
+        assert!(metadata.file_type().is_socket());
+        fs::remove_file(&live.path).unwrap();
+
+        fs::write(&socket.path, "").unwrap();
+        assert_eq!(
+            bind(&socket).unwrap_err().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        fs::remove_file(&socket.path).unwrap();
+    }
+}
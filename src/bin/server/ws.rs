@@ -0,0 +1,293 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    http::HeaderMap,
+    response::Response,
+};
+use myco_kv::{
+    errors::TransactionError,
+    events::KeyEvent,
+    kvmap::KVMap,
+    operation::Operation,
+    session::{Protocol, Session},
+};
+use serde_json::json;
+use tokio::{
+    sync::{
+        broadcast::{error::RecvError, Receiver},
+        mpsc::{self, error::TrySendError},
+    },
+    task::JoinHandle,
+};
+
+use super::http::{self, Gateway};
+
+/// The number of messages waiting to be sent on a socket before its subscriptions stop
+/// queueing changes and send a fresh snapshot once there is room.
+const QUEUE_CAPACITY: usize = 256;
+
+/// Authenticates the user before upgrading, so a socket is only opened for valid
+/// credentials.
+pub(super) async fn upgrade(
+    State(gateway): State<Gateway>,
+    headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    match gateway.authenticate(&headers).await {
+        Ok(session) => upgrade.on_upgrade(move |socket| handle_socket(socket, gateway, session)),
+        Err(e) => http::error_response(e),
+    }
+}
+
+/// Accepts `SUBSCRIBE <key>` and `UNSUBSCRIBE <key>` messages, and sends the current value
+/// of each subscribed key or subtree followed by every change made to it. Subscribing
+/// requires permission to read the key.
+async fn handle_socket(mut socket: WebSocket, gateway: Gateway, session: Session) {
+    let (sender, mut messages) = mpsc::channel(QUEUE_CAPACITY);
+    let mut subscriptions: HashMap<String, JoinHandle<()>> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            request = socket.recv() => {
+                let request = match request {
+                    Some(Ok(Message::Text(request))) => request,
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => {
+                        eprintln!("Failed to read from WebSocket: {}", e);
+                        break;
+                    }
+                };
+
+                let mut parts = request.split_whitespace();
+                let result = match (parts.next(), parse_key(parts.next())) {
+                    (_, Err(e)) => Err(e),
+                    (Some("SUBSCRIBE"), Ok(key)) => session
+                        .authorize(&Operation::Get(key.to_string()))
+                        .map(|_| {
+                            let task = tokio::spawn(forward(
+                            gateway.clone(),
+                            key.to_string(),
+                            sender.clone(),
+                        ));
+                            if let Some(previous) = subscriptions.insert(key.to_string(), task) {
+                                previous.abort();
+                            }
+                        }),
+                    (Some("UNSUBSCRIBE"), Ok(key)) => match subscriptions.remove(key) {
+                        Some(task) => {
+                            task.abort();
+                            Ok(())
+                        }
+                        None => Err(TransactionError::OperationFailure(format!(
+                            "not subscribed to {}",
+                            key
+                        ))),
+                    },
+                    (command, Ok(_)) => Err(TransactionError::UnknownCommand(
+                        command.unwrap_or_default().to_string(),
+                    )),
+                };
+
+                if let Err(e) = result {
+                    let error = Protocol::Typed.format(Err(e));
+                    if socket.send(Message::Text(error.into())).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            Some(message) = messages.recv() => {
+                if socket.send(Message::Text(message.into())).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    for task in subscriptions.into_values() {
+        task.abort();
+    }
+}
+
+/// Subscriptions are to a single key, or to a subtree with `.*`.
+fn parse_key(key: Option<&str>) -> Result<&str, TransactionError> {
+    let key = key.ok_or(TransactionError::MissingKey)?;
+    match key.strip_suffix(".*").unwrap_or(key).contains('*') {
+        true => Err(TransactionError::InvalidKey(key.to_string())),
+        false => Ok(key),
+    }
+}
+
+/// Sends a snapshot of the key followed by its changes, until the receiving end is gone.
+/// A subscriber that falls too far behind, or whose socket cannot keep up, is sent a
+/// fresh snapshot.
+async fn forward(gateway: Gateway, key: String, sender: mpsc::Sender<String>) {
+    let head = key.strip_suffix(".*").unwrap_or(&key).to_string();
+
+    loop {
+        let permit = gateway.workers.clone().acquire_owned().await.unwrap();
+        let kvmap = Arc::clone(&gateway.kvmap);
+        let snapshot_key = key.clone();
+        let task = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            subscribe(&kvmap, snapshot_key)
+        });
+        let (mut events, snapshot) = match task.await {
+            Ok(Ok(subscription)) => subscription,
+            Ok(Err(e)) => {
+                sender
+                    .send(Protocol::Typed.format(Err(e)))
+                    .await
+                    .unwrap_or(());
+                return;
+            }
+            Err(_) => return,
+        };
+        if sender.send(snapshot).await.is_err() {
+            return;
+        }
+
+        loop {
+            match events.recv().await {
+                Ok(event) if event.is_under(&head) => {
+                    match sender.try_send(event.to_json().to_string()) {
+                        Ok(()) => {}
+                        Err(TrySendError::Full(_)) => break,
+                        Err(TrySendError::Closed(_)) => return,
+                    }
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(_)) => break,
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+}
+
+/// Subscribes to changes and reads the key's current value under the same lock, so no
+/// change is missed or included twice.
+fn subscribe(
+    kvmap: &RwLock<KVMap>,
+    key: String,
+) -> Result<(Receiver<KeyEvent>, String), TransactionError> {
+    let (events, snapshot) =
+        KVMap::read_shared(kvmap, |kvmap| (kvmap.subscribe(), kvmap.snapshot()))?;
+
+    let value = match snapshot.read(Operation::Get(key.clone())) {
+        Ok(reply) => reply.to_json().1,
+        Err(TransactionError::KeyNotFound(_)) => serde_json::Value::Null,
+        Err(e) => return Err(e),
+    };
+    let snapshot = json!({ "event": "snapshot", "key": key, "value": value });
+
+    Ok((events, snapshot.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use myco_kv::{acl::AccessControl, operation::value::Value, wal::WriteAheadLog};
+    use tokio::sync::Semaphore;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn forwards_snapshot_and_changes() {
+        let wal = Arc::new(Mutex::new(
+            WriteAheadLog::new("log.server.ws.test.txt").unwrap(),
+        ));
+        wal.lock().unwrap().clear().unwrap();
+        let kvmap = Arc::new(RwLock::new(KVMap::new(wal)));
+        let gateway = Gateway {
+            kvmap: Arc::clone(&kvmap),
+            access: Arc::new(AccessControl::new()),
+            workers: Arc::new(Semaphore::new(1)),
+        };
+        let process = |command: &str| {
+            let operation = Operation::parse(command.to_string()).unwrap();
+            kvmap.write().unwrap().process_operation(operation).unwrap();
+        };
+
+        process(r#"PUT config.frontend.theme "light""#);
+        let (sender, mut messages) = mpsc::channel(QUEUE_CAPACITY);
+        let task = tokio::spawn(forward(gateway, "config.frontend.*".to_string(), sender));
+
+        assert_eq!(
+            messages.recv().await.unwrap(),
+            r#"{"event":"snapshot","key":"config.frontend.*","value":{"theme":"light"}}"#
+        );
+
+        process(r#"PUT config.backend.port 8080"#);
+        process(r#"PUT config.frontend.theme "dark""#);
+        process("DELETE config.frontend.theme");
+        assert_eq!(
+            messages.recv().await.unwrap(),
+            r#"{"event":"put","key":"config.frontend.theme","value":"dark"}"#
+        );
+        assert_eq!(
+            messages.recv().await.unwrap(),
+            r#"{"event":"delete","key":"config.frontend.theme"}"#
+        );
+
+        process(r#"PUT config.frontend.layout "grid""#);
+        process("PURGE");
+        assert_eq!(
+            messages.recv().await.unwrap(),
+            r#"{"event":"put","key":"config.frontend.layout","value":"grid"}"#
+        );
+        assert_eq!(
+            messages.recv().await.unwrap(),
+            r#"{"event":"delete","key":"config.frontend.layout"}"#
+        );
+
+        drop(messages);
+        process(r#"PUT config.frontend.theme "light""#);
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn sends_a_snapshot_when_the_queue_is_full() {
+        let wal = Arc::new(Mutex::new(
+            WriteAheadLog::new("log.server.ws.full.test.txt").unwrap(),
+        ));
+        wal.lock().unwrap().clear().unwrap();
+        let kvmap = Arc::new(RwLock::new(KVMap::new(wal)));
+        let gateway = Gateway {
+            kvmap: Arc::clone(&kvmap),
+            access: Arc::new(AccessControl::new()),
+            workers: Arc::new(Semaphore::new(1)),
+        };
+
+        let (sender, mut messages) = mpsc::channel(2);
+        let task = tokio::spawn(forward(gateway, "counter".to_string(), sender));
+        assert_eq!(
+            messages.recv().await.unwrap(),
+            r#"{"event":"snapshot","key":"counter","value":null}"#
+        );
+
+        {
+            let mut kvmap = kvmap.write().unwrap();
+            for value in 1..=3 {
+                let operation = Operation::Put("counter".to_string(), Value::Integer(value));
+                kvmap.process_operation(operation).unwrap();
+            }
+        }
+        for expected in [
+            r#"{"event":"put","key":"counter","value":1}"#,
+            r#"{"event":"put","key":"counter","value":2}"#,
+            r#"{"event":"snapshot","key":"counter","value":3}"#,
+        ] {
+            assert_eq!(messages.recv().await.unwrap(), expected);
+        }
+
+        task.abort();
+    }
+}
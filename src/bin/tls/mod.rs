@@ -0,0 +1,80 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    ClientConfig, RootCertStore, ServerConfig,
+};
+
+/// The certificate and key the server presents, and the authority that signs client
+/// certificates if clients must present one.
+pub struct ServerFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>,
+}
+
+/// The authority that signs the server's certificate, and the certificate and key to
+/// present if the server asks for one.
+pub struct ClientFiles {
+    pub ca: PathBuf,
+    pub identity: Option<(PathBuf, PathBuf)>,
+}
+
+pub fn server_config(files: &ServerFiles) -> io::Result<Arc<ServerConfig>> {
+    let builder = match &files.client_ca {
+        Some(client_ca) => {
+            let verifier = WebPkiClientVerifier::builder(Arc::new(load_roots(client_ca)?))
+                .build()
+                .map_err(io::Error::other)?;
+            ServerConfig::builder().with_client_cert_verifier(verifier)
+        }
+        None => ServerConfig::builder().with_no_client_auth(),
+    };
+
+    let config = builder
+        .with_single_cert(load_certs(&files.cert)?, load_key(&files.key)?)
+        .map_err(io::Error::other)?;
+    Ok(Arc::new(config))
+}
+
+pub fn client_config(files: &ClientFiles) -> io::Result<Arc<ClientConfig>> {
+    let builder = ClientConfig::builder().with_root_certificates(load_roots(&files.ca)?);
+
+    let config = match &files.identity {
+        Some((cert, key)) => builder
+            .with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+            .map_err(io::Error::other)?,
+        None => builder.with_no_client_auth(),
+    };
+    Ok(Arc::new(config))
+}
+
+fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid_file(path, e))
+}
+
+fn load_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path).map_err(|e| invalid_file(path, e))
+}
+
+fn load_roots(path: &Path) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert).map_err(|e| invalid_file(path, e))?;
+    }
+    Ok(roots)
+}
+
+fn invalid_file(path: &Path, error: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {}", path.display(), error),
+    )
+}
@@ -0,0 +1,108 @@
+use std::{
+    sync::{Condvar, Mutex},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+/// A source of the current time, measured in milliseconds since the UNIX epoch.
+///
+/// Every time-dependent part of the database (expirations, `TIME`, background workers)
+/// reads the time through a `Clock` so that it can be swapped out for a `ManualClock` in
+/// tests.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> i64;
+
+    /// Blocks the calling thread until `now()` reaches the given timestamp.
+    fn sleep_until(&self, timestamp: i64);
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64
+    }
+
+    fn sleep_until(&self, timestamp: i64) {
+        let remaining = timestamp - self.now();
+        if remaining > 0 {
+            thread::sleep(Duration::from_millis(remaining as u64));
+        }
+    }
+}
+
+/// A clock that only moves when told to, for deterministic tests and for replaying the
+/// log. Threads sleeping on a
+/// `ManualClock` wake up once it has been advanced past their deadline.
+pub struct ManualClock {
+    now: Mutex<i64>,
+    changed: Condvar,
+}
+
+impl ManualClock {
+    pub fn new(now: i64) -> Self {
+        ManualClock {
+            now: Mutex::new(now),
+            changed: Condvar::new(),
+        }
+    }
+
+    pub fn set(&self, now: i64) {
+        *self.now.lock().unwrap() = now;
+        self.changed.notify_all();
+    }
+
+    pub fn advance(&self, millis: i64) {
+        *self.now.lock().unwrap() += millis;
+        self.changed.notify_all();
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> i64 {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep_until(&self, timestamp: i64) {
+        let now = self.now.lock().unwrap();
+        let _now = self
+            .changed
+            .wait_while(now, |now| *now < timestamp)
+            .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn manual_clock_advances() {
+        let clock = ManualClock::new(1000);
+        assert_eq!(clock.now(), 1000);
+        clock.advance(500);
+        assert_eq!(clock.now(), 1500);
+        clock.set(10);
+        assert_eq!(clock.now(), 10);
+    }
+
+    #[test]
+    fn manual_clock_wakes_sleepers() {
+        let clock = Arc::new(ManualClock::new(0));
+        let sleeper_clock = Arc::clone(&clock);
+        let sleeper = thread::spawn(move || {
+            sleeper_clock.sleep_until(100);
+            sleeper_clock.now()
+        });
+
+        clock.advance(50);
+        clock.advance(50);
+
+        assert_eq!(sleeper.join().unwrap(), 100);
+    }
+}
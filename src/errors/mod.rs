@@ -15,6 +15,11 @@ pub enum TransactionError {
     SerializationFailure,
     MissingCommand,
     InvalidExpiration(i64),
+    IndexNotFound(String),
+    KeyExists(String),
+    AuthenticationRequired,
+    PermissionDenied(String),
+    AuthenticationFailed,
 }
 
 impl TransactionError {
@@ -75,16 +80,27 @@ impl TransactionError {
                 format!("{}: Invalid command", self.get_code())
             }
             TransactionError::InvalidExpiration(timestamp) => {
-                format!(
-                    "{}: Invalid expiration {}",
-                    self.get_code(),
-                    timestamp.to_string()
-                )
+                format!("{}: Invalid expiration {}", self.get_code(), timestamp)
+            }
+            TransactionError::IndexNotFound(pattern) => {
+                format!("{}: No index exists for {}", self.get_code(), pattern)
+            }
+            TransactionError::KeyExists(key) => {
+                format!("{}: Key {} already exists", self.get_code(), key)
+            }
+            TransactionError::AuthenticationRequired => {
+                format!("{}: Authentication required", self.get_code())
+            }
+            TransactionError::PermissionDenied(message) => {
+                format!("{}: Permission denied - {}", self.get_code(), message)
+            }
+            TransactionError::AuthenticationFailed => {
+                format!("{}: Invalid username or password", self.get_code())
             }
         }
     }
 
-    fn get_code(&self) -> String {
+    pub fn get_code(&self) -> String {
         match self {
             TransactionError::UnknownCommand(_) => String::from("E01"),
             TransactionError::MissingKey => String::from("E02"),
@@ -101,6 +117,11 @@ impl TransactionError {
             TransactionError::SerializationFailure => String::from("E13"),
             TransactionError::MissingCommand => String::from("E14"),
             TransactionError::InvalidExpiration(_) => String::from("E15"),
+            TransactionError::IndexNotFound(_) => String::from("E16"),
+            TransactionError::KeyExists(_) => String::from("E17"),
+            TransactionError::AuthenticationRequired => String::from("E18"),
+            TransactionError::PermissionDenied(_) => String::from("E19"),
+            TransactionError::AuthenticationFailed => String::from("E20"),
         }
     }
 }
@@ -0,0 +1,62 @@
+use serde_json::json;
+
+use crate::operation::value::Value;
+
+/// The number of events kept for subscribers that have not received them yet. Slower
+/// subscribers miss events and are told they lagged.
+pub const CAPACITY: usize = 1024;
+
+/// A change to a single key.
+#[derive(Debug, PartialEq, Clone)]
+pub enum KeyEvent {
+    Put(String, Value),
+    Delete(String),
+    Expire(String),
+}
+
+impl KeyEvent {
+    pub fn key(&self) -> &str {
+        match self {
+            KeyEvent::Put(key, _) | KeyEvent::Delete(key) | KeyEvent::Expire(key) => key,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyEvent::Put(_, _) => "put",
+            KeyEvent::Delete(_) => "delete",
+            KeyEvent::Expire(_) => "expire",
+        }
+    }
+
+    /// Returns whether the event is for the key, or for a key beneath it.
+    pub fn is_under(&self, key: &str) -> bool {
+        let event_key = self.key();
+        match event_key.strip_prefix(key) {
+            Some(rest) => rest.is_empty() || rest.starts_with('.'),
+            None => false,
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            KeyEvent::Put(key, value) => json!({ "event": "put", "key": key, "value": value }),
+            _ => json!({ "event": self.name(), "key": self.key() }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn matches_keys_by_segment() {
+        let event = KeyEvent::Delete("config.frontend.theme".to_string());
+
+        assert!(event.is_under("config.frontend"));
+        assert!(event.is_under("config.frontend.theme"));
+        assert!(!event.is_under("config.front"));
+        assert!(!event.is_under("config.frontend.theme.dark"));
+    }
+}
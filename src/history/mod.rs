@@ -0,0 +1,227 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde_json::json;
+
+use crate::{errors::TransactionError, operation::value::Value};
+
+/// The number of revisions kept for each key unless configured otherwise.
+pub const DEFAULT_LIMIT: usize = 16;
+
+/// How long, in milliseconds, the revisions of a deleted key are kept unless configured
+/// otherwise.
+pub const DEFAULT_RETENTION: i64 = 60 * 60 * 1000;
+
+/// A value a key held from `timestamp` onwards, or `None` if the key was deleted then.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Revision {
+    pub timestamp: i64,
+    pub value: Option<Value>,
+}
+
+impl Revision {
+    pub fn to_json(&self) -> serde_json::Value {
+        match &self.value {
+            Some(value) => json!({ "timestamp": self.timestamp, "value": value }),
+            None => json!({ "timestamp": self.timestamp, "deleted": true }),
+        }
+    }
+}
+
+/// The most recent revisions of every key, oldest first, up to a fixed number per key.
+/// A deleted key's revisions are dropped once it has stayed deleted for the retention
+/// period, and a limit of 0 keeps no history at all.
+pub struct History {
+    limit: usize,
+    retention: i64,
+    revisions: HashMap<String, VecDeque<Revision>>,
+    deletions: VecDeque<(i64, String)>,
+}
+
+impl History {
+    pub fn new(limit: usize, retention: i64) -> Self {
+        History {
+            limit,
+            retention,
+            revisions: HashMap::new(),
+            deletions: VecDeque::new(),
+        }
+    }
+
+    pub fn record(&mut self, key: &str, timestamp: i64, value: Option<Value>) {
+        if self.limit == 0 {
+            return;
+        }
+
+        self.prune(timestamp);
+        if value.is_none() {
+            self.deletions.push_back((timestamp, key.to_string()));
+        }
+        let revisions = self.revisions.entry(key.to_string()).or_default();
+        revisions.push_back(Revision { timestamp, value });
+        if revisions.len() > self.limit {
+            revisions.pop_front();
+        }
+    }
+
+    /// Drops the revisions of keys deleted longer than the retention period before `now`
+    /// that have not been set again since.
+    fn prune(&mut self, now: i64) {
+        while let Some((timestamp, _)) = self.deletions.front() {
+            if now.saturating_sub(*timestamp) <= self.retention {
+                break;
+            }
+
+            let (timestamp, key) = self.deletions.pop_front().unwrap();
+            let latest = self
+                .revisions
+                .get(&key)
+                .and_then(|revisions| revisions.back());
+            if latest.is_some_and(|latest| latest.value.is_none() && latest.timestamp == timestamp)
+            {
+                self.revisions.remove(&key);
+            }
+        }
+    }
+
+    /// Returns up to `limit` revisions of the key, newest first.
+    pub fn revisions(
+        &self,
+        key: &str,
+        limit: Option<usize>,
+    ) -> Result<Vec<&Revision>, TransactionError> {
+        self.check_enabled()?;
+        let revisions = self
+            .revisions
+            .get(key)
+            .ok_or(TransactionError::KeyNotFound(key.to_string()))?;
+
+        Ok(revisions
+            .iter()
+            .rev()
+            .take(limit.unwrap_or(usize::MAX))
+            .collect())
+    }
+
+    /// Returns the value the key held at the timestamp. Fails if the key did not exist
+    /// then, or if its oldest kept revision is more recent than the timestamp.
+    pub fn value_at(&self, key: &str, timestamp: i64) -> Result<&Value, TransactionError> {
+        self.check_enabled()?;
+        let not_found = || TransactionError::KeyNotFound(key.to_string());
+        let revisions = self.revisions.get(key).ok_or_else(not_found)?;
+
+        match revisions
+            .iter()
+            .rev()
+            .find(|revision| revision.timestamp <= timestamp)
+        {
+            Some(revision) => revision.value.as_ref().ok_or_else(not_found),
+            None if revisions.len() >= self.limit => Err(TransactionError::OperationFailure(
+                format!("history of {} does not go back to {}", key, timestamp),
+            )),
+            None => Err(not_found()),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.revisions.clear();
+        self.deletions.clear();
+    }
+
+    fn check_enabled(&self) -> Result<(), TransactionError> {
+        match self.limit {
+            0 => Err(TransactionError::OperationFailure(
+                "history is disabled".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keeps_bounded_revisions() {
+        let mut history = History::new(2, DEFAULT_RETENTION);
+        history.record("a", 1, Some(Value::Integer(1)));
+        history.record("a", 2, None);
+        history.record("a", 3, Some(Value::Integer(3)));
+
+        assert_eq!(
+            history.revisions("a", None).unwrap(),
+            vec![
+                &Revision {
+                    timestamp: 3,
+                    value: Some(Value::Integer(3)),
+                },
+                &Revision {
+                    timestamp: 2,
+                    value: None,
+                },
+            ]
+        );
+        assert_eq!(history.revisions("a", Some(1)).unwrap().len(), 1);
+        assert_eq!(
+            history.revisions("b", None),
+            Err(TransactionError::KeyNotFound("b".to_string()))
+        );
+    }
+
+    #[test]
+    fn finds_values_as_of_timestamp() {
+        let mut history = History::new(3, DEFAULT_RETENTION);
+        history.record("a", 10, Some(Value::Integer(1)));
+        history.record("a", 20, None);
+        history.record("a", 30, Some(Value::Integer(3)));
+
+        assert_eq!(history.value_at("a", 15), Ok(&Value::Integer(1)));
+        assert_eq!(history.value_at("a", 30), Ok(&Value::Integer(3)));
+        assert_eq!(
+            history.value_at("a", 25),
+            Err(TransactionError::KeyNotFound("a".to_string()))
+        );
+        assert!(matches!(
+            history.value_at("a", 5),
+            Err(TransactionError::OperationFailure(_))
+        ));
+
+        history.record("b", 10, Some(Value::Integer(1)));
+        assert_eq!(
+            history.value_at("b", 5),
+            Err(TransactionError::KeyNotFound("b".to_string()))
+        );
+    }
+
+    #[test]
+    fn drops_deleted_keys_once_retention_passes() {
+        let mut history = History::new(3, 100);
+        history.record("a", 10, Some(Value::Integer(1)));
+        history.record("a", 20, None);
+        history.record("b", 20, None);
+        history.record("b", 50, Some(Value::Integer(2)));
+
+        history.record("c", 120, Some(Value::Integer(3)));
+        assert_eq!(history.revisions("a", None).unwrap().len(), 2);
+
+        history.record("c", 121, Some(Value::Integer(4)));
+        assert_eq!(
+            history.revisions("a", None),
+            Err(TransactionError::KeyNotFound("a".to_string()))
+        );
+        assert_eq!(history.revisions("b", None).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn keeps_nothing_when_disabled() {
+        let mut history = History::new(0, DEFAULT_RETENTION);
+        history.record("a", 10, Some(Value::Integer(1)));
+
+        assert_eq!(
+            history.value_at("a", 10),
+            Err(TransactionError::OperationFailure(
+                "history is disabled".to_string()
+            ))
+        );
+    }
+}
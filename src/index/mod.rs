@@ -0,0 +1,200 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::{errors::TransactionError, operation::value::Value};
+
+/// A reverse lookup from the values of keys matching a wildcard pattern, such as
+/// `users.*.email`, to the parent keys holding them, such as `users.42`.
+pub struct Index {
+    parts: Vec<String>,
+    entries: HashMap<EntryKey, BTreeSet<String>>,
+}
+
+/// Values are looked up by their type as well as their text, as an integer, a float and a
+/// string can all print as `1`.
+type EntryKey = (&'static str, String);
+
+fn entry_key(value: &Value) -> EntryKey {
+    (value.type_name(), value.to_string())
+}
+
+impl Index {
+    /// Creates an empty index. The pattern must contain a `*` before its last segment and
+    /// must end in a plain segment.
+    pub fn new(pattern: &str) -> Result<Self, TransactionError> {
+        let parts: Vec<String> = pattern.split('.').map(|part| part.to_string()).collect();
+        let (last, parents) = parts.split_last().unwrap();
+
+        let valid = parents.iter().any(|part| part == "*")
+            && !last.starts_with('*')
+            && parts
+                .iter()
+                .all(|part| part == "*" || !part.starts_with('*'));
+        if !valid {
+            return Err(TransactionError::InvalidKey(pattern.to_string()));
+        }
+
+        Ok(Index {
+            parts,
+            entries: HashMap::new(),
+        })
+    }
+
+    pub fn matches(&self, key: &str) -> bool {
+        let mut parts = key.split('.');
+        for expected in &self.parts {
+            match parts.next() {
+                Some(part) if expected == "*" || expected == part => continue,
+                _ => return false,
+            }
+        }
+        parts.next().is_none()
+    }
+
+    pub fn insert(&mut self, key: &str, value: &Value) {
+        if let Some((parent, _)) = key.rsplit_once('.') {
+            self.entries
+                .entry(entry_key(value))
+                .or_default()
+                .insert(parent.to_string());
+        }
+    }
+
+    pub fn remove(&mut self, key: &str, value: &Value) {
+        let value = entry_key(value);
+        if let (Some((parent, _)), Some(parents)) =
+            (key.rsplit_once('.'), self.entries.get_mut(&value))
+        {
+            parents.remove(parent);
+            if parents.is_empty() {
+                self.entries.remove(&value);
+            }
+        }
+    }
+
+    /// Returns the parent keys whose indexed field holds the value, in sorted order.
+    pub fn find(&self, value: &Value) -> Vec<String> {
+        self.entries
+            .get(&entry_key(value))
+            .map(|parents| parents.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// All indexes declared on a database, keyed by their pattern.
+#[derive(Default)]
+pub struct IndexSet {
+    indexes: BTreeMap<String, Index>,
+}
+
+impl IndexSet {
+    pub fn new() -> Self {
+        IndexSet {
+            indexes: BTreeMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.indexes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indexes.is_empty()
+    }
+
+    pub fn contains(&self, pattern: &str) -> bool {
+        self.indexes.contains_key(pattern)
+    }
+
+    pub fn create(&mut self, pattern: &str, index: Index) {
+        self.indexes.insert(pattern.to_string(), index);
+    }
+
+    pub fn drop_index(&mut self, pattern: &str) -> Result<(), TransactionError> {
+        self.indexes
+            .remove(pattern)
+            .map(|_| ())
+            .ok_or(TransactionError::IndexNotFound(pattern.to_string()))
+    }
+
+    pub fn get(&self, pattern: &str) -> Result<&Index, TransactionError> {
+        self.indexes
+            .get(pattern)
+            .ok_or(TransactionError::IndexNotFound(pattern.to_string()))
+    }
+
+    pub fn on_put(&mut self, key: &str, old_value: Option<&Value>, value: &Value) {
+        for index in self.indexes.values_mut() {
+            if index.matches(key) {
+                if let Some(old_value) = old_value {
+                    index.remove(key, old_value);
+                }
+                index.insert(key, value);
+            }
+        }
+    }
+
+    pub fn on_delete(&mut self, key: &str, old_value: &Value) {
+        for index in self.indexes.values_mut() {
+            if index.matches(key) {
+                index.remove(key, old_value);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.indexes.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn validates_patterns() {
+        assert!(Index::new("users.*.email").is_ok());
+        assert!(Index::new("orders.*.items.*.sku").is_ok());
+        assert!(Index::new("users.email").is_err());
+        assert!(Index::new("users.*").is_err());
+        assert!(Index::new("users.*2.email").is_err());
+    }
+
+    #[test]
+    fn maintains_reverse_lookup() {
+        let mut indexes = IndexSet::new();
+        indexes.create("users.*.email", Index::new("users.*.email").unwrap());
+
+        let email = Value::String("a@b.com".to_string());
+        indexes.on_put("users.1.email", None, &email);
+        indexes.on_put("users.2.email", None, &email);
+        indexes.on_put("users.3.name", None, &email);
+        indexes.on_put("users.3.email.old", None, &email);
+
+        let index = indexes.get("users.*.email").unwrap();
+        assert_eq!(index.find(&email), vec!["users.1", "users.2"]);
+
+        let other = Value::String("c@d.com".to_string());
+        indexes.on_put("users.1.email", Some(&email), &other);
+        indexes.on_delete("users.2.email", &email);
+
+        let index = indexes.get("users.*.email").unwrap();
+        assert!(index.find(&email).is_empty());
+        assert_eq!(index.find(&other), vec!["users.1"]);
+    }
+
+    #[test]
+    fn keeps_values_of_different_types_apart() {
+        let mut index = Index::new("items.*.code").unwrap();
+        index.insert("items.1.code", &Value::Integer(1));
+        index.insert("items.2.code", &Value::Float(1.0));
+        index.insert("items.3.code", &Value::String("1".to_string()));
+
+        assert_eq!(index.find(&Value::Integer(1)), vec!["items.1"]);
+        assert_eq!(index.find(&Value::Float(1.0)), vec!["items.2"]);
+        assert_eq!(index.find(&Value::String("1".to_string())), vec!["items.3"]);
+
+        index.remove("items.2.code", &Value::Float(1.0));
+        assert!(index.find(&Value::Float(1.0)).is_empty());
+        assert_eq!(index.find(&Value::Integer(1)), vec!["items.1"]);
+    }
+}
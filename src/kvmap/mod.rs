@@ -1,44 +1,89 @@
+use crate::aggregate::Aggregate;
 use crate::atomicheap::AtomicHeap;
+use crate::clock::{Clock, ManualClock, SystemClock};
 use crate::errors::TransactionError;
+use crate::events::{self, KeyEvent};
+use crate::history::{self, History};
+use crate::index::{Index, IndexSet};
 use crate::operation::expiration::Expiration;
-use crate::operation::{value::Value, Operation};
+use crate::operation::{order::Order, reply::Reply, value::Value, Operation};
+use crate::pattern::PatternSyntax;
+use crate::pubsub::Message;
+use crate::query::Predicate;
 use crate::radixtree::RadixTree;
-use crate::wal::WriteAheadLog;
-use std::sync::{Arc, Mutex};
-use std::time::SystemTime;
+use crate::snapshot::Snapshot;
+use crate::wal::{self, WriteAheadLog};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::broadcast;
 
 pub struct KVMap {
     radix_tree: RadixTree,
     wal: Arc<Mutex<WriteAheadLog>>,
     exp_heap: AtomicHeap<Expiration>,
+    clock: Arc<dyn Clock>,
+    indexes: IndexSet,
+    history: History,
+    events: broadcast::Sender<KeyEvent>,
+    messages: broadcast::Sender<Message>,
 }
 
 impl KVMap {
     pub fn new(wal: Arc<Mutex<WriteAheadLog>>) -> Self {
+        KVMap::with_clock(wal, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(wal: Arc<Mutex<WriteAheadLog>>, clock: Arc<dyn Clock>) -> Self {
         KVMap {
             radix_tree: RadixTree::new(),
             wal,
             exp_heap: AtomicHeap::new(),
+            clock,
+            indexes: IndexSet::new(),
+            history: History::new(history::DEFAULT_LIMIT, history::DEFAULT_RETENTION),
+            events: broadcast::channel(events::CAPACITY).0,
+            messages: broadcast::channel(events::CAPACITY).0,
         }
     }
 
+    /// Keeps up to `limit` revisions of each key, and the revisions of a deleted key for
+    /// `retention` milliseconds. A limit of 0 turns history off. Any history kept so far
+    /// is discarded, so this should be set before restoring.
+    pub fn set_history(&mut self, limit: usize, retention: i64) {
+        self.history = History::new(limit, retention);
+    }
+
+    /// Replays the log. Changes are dated by the timestamp each line was logged with, or
+    /// by the previous timestamp for lines from older logs without one. Expirations are
+    /// not logged, so they are processed as the replayed time passes them.
     pub fn restore(&mut self) -> Result<(), TransactionError> {
+        let replay_clock = Arc::new(ManualClock::new(0));
+        let clock = std::mem::replace(&mut self.clock, replay_clock.clone());
+        let result = self.replay(&replay_clock);
+        self.clock = clock;
+        result
+    }
+
+    fn replay(&mut self, clock: &ManualClock) -> Result<(), TransactionError> {
         let line_iter = self
             .wal
             .lock()
             .unwrap()
             .read_all_lines()
-            .map_err(|error| match error {
-                err => TransactionError::RestoreError(err.message()),
-            })?;
+            .map_err(|err| TransactionError::RestoreError(err.message()))?;
 
         for line in line_iter {
-            let operation = Operation::parse(line.unwrap()).map_err(|error| match error {
-                err => TransactionError::RestoreError(err.message()),
-            })?;
+            let (timestamp, operation) = wal::parse_entry(&line.unwrap())
+                .map_err(|err| TransactionError::RestoreError(err.message()))?;
+            if let Some(timestamp) = timestamp {
+                clock.set(timestamp);
+                self.process_expirations()
+                    .map_err(|err| TransactionError::RestoreError(err.message()))?;
+            }
 
             let result: Result<(), TransactionError> = match operation {
                 Operation::Get(_) => Ok(()),
+                Operation::GetOrdered(_, _, _) => Ok(()),
                 Operation::Put(key, value) => {
                     if let Err(error) = self.put(key, value) {
                         return Err(TransactionError::RestoreError(error.message()));
@@ -51,49 +96,345 @@ impl KVMap {
                     }
                     Ok(())
                 }
+                Operation::Expire(_, _) => Err(TransactionError::RestoreError(
+                    "relative expiration found in log".to_string(),
+                )),
                 Operation::ExpireAt(expiration) => {
                     if let Err(error) = self.expire_at(expiration) {
                         return Err(TransactionError::RestoreError(error.message()));
                     }
                     Ok(())
                 }
+                Operation::Persist(key) => {
+                    if let Err(error) = self.persist(&key) {
+                        return Err(TransactionError::RestoreError(error.message()));
+                    }
+                    Ok(())
+                }
+                Operation::Batch(operations) => {
+                    if let Err(error) = self.apply_batch(operations) {
+                        return Err(TransactionError::RestoreError(error.message()));
+                    }
+                    Ok(())
+                }
                 Operation::Time => Ok(()),
                 Operation::Purge => Ok(()),
+                Operation::Keys(_, _) => Ok(()),
+                Operation::Scan(_, _, _) => Ok(()),
+                Operation::Range(_, _, _, _) => Ok(()),
+                Operation::Query(_, _) => Ok(()),
+                Operation::Index(pattern) => {
+                    if let Err(error) = self.create_index(&pattern) {
+                        return Err(TransactionError::RestoreError(error.message()));
+                    }
+                    Ok(())
+                }
+                Operation::DropIndex(pattern) => {
+                    if let Err(error) = self.drop_index(&pattern) {
+                        return Err(TransactionError::RestoreError(error.message()));
+                    }
+                    Ok(())
+                }
+                Operation::Find(_, _) => Ok(()),
+                Operation::Aggregate(_, _, _) => Ok(()),
+                Operation::Info => Ok(()),
+                Operation::Rename(from, to) => {
+                    if let Err(error) = self.rename(&from, &to) {
+                        return Err(TransactionError::RestoreError(error.message()));
+                    }
+                    Ok(())
+                }
+                Operation::Move(from, to) => {
+                    if let Err(error) = self.move_subtree(&from, &to) {
+                        return Err(TransactionError::RestoreError(error.message()));
+                    }
+                    Ok(())
+                }
+                Operation::Copy(from, to, ttl) => {
+                    if let Err(error) = self.copy(&from, &to, ttl) {
+                        return Err(TransactionError::RestoreError(error.message()));
+                    }
+                    Ok(())
+                }
+                Operation::History(_, _) => Ok(()),
+                Operation::GetAsOf(_, _) => Ok(()),
+                Operation::Snapshot(_) => Ok(()),
+                Operation::SnapshotBegin => Ok(()),
+                Operation::SnapshotEnd => Ok(()),
+                Operation::Ttl(_) => Ok(()),
+                Operation::Hello(_) => Ok(()),
+                Operation::Publish(_, _) => Ok(()),
+                Operation::Auth(_, _) | Operation::Acl(_) => Ok(()),
+                Operation::Subscribe(_)
+                | Operation::Unsubscribe(_)
+                | Operation::PSubscribe(_)
+                | Operation::PUnsubscribe(_) => Ok(()),
+                Operation::Incr(_) => Err(TransactionError::RestoreError(
+                    "unresolved increment found in log".to_string(),
+                )),
             };
 
-            result.map_err(|error| match error {
-                err => TransactionError::RestoreError(err.message()),
-            })?;
+            result.map_err(|err| TransactionError::RestoreError(err.message()))?;
         }
 
         Ok(())
     }
 
-    pub fn get(&mut self, key: &str) -> Result<String, TransactionError> {
-        let result = self.radix_tree.get(key);
+    /// Returns a consistent view of the data as it is now. Taking a snapshot is cheap, as
+    /// it shares its structure with the database until either changes.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(self.radix_tree.clone())
+    }
+
+    pub fn get(&self, key: &str) -> Result<Reply, TransactionError> {
+        self.snapshot().get(key)
+    }
+
+    pub fn get_ordered(
+        &self,
+        key: &str,
+        order: Order,
+        limit: Option<usize>,
+    ) -> Result<String, TransactionError> {
+        self.snapshot().get_ordered(key, order, limit)
+    }
+
+    pub fn range(
+        &self,
+        start: &str,
+        end: &str,
+        order: Order,
+        limit: Option<usize>,
+    ) -> Result<String, TransactionError> {
+        self.snapshot().range(start, end, order, limit)
+    }
+
+    pub fn query(
+        &self,
+        prefix: &str,
+        predicates: &[Predicate],
+    ) -> Result<String, TransactionError> {
+        self.snapshot().query(prefix, predicates)
+    }
+
+    pub fn aggregate(
+        &self,
+        aggregate: Aggregate,
+        key: &str,
+        strict: bool,
+    ) -> Result<Reply, TransactionError> {
+        self.snapshot().aggregate(aggregate, key, strict)
+    }
+
+    /// Returns the number of keys, pending expirations and indexes as JSON.
+    pub fn info(&self) -> Result<String, TransactionError> {
+        let info = serde_json::json!({
+            "keys": self.radix_tree.len(),
+            "expires": self.exp_heap.len(),
+            "indexes": self.indexes.len(),
+        });
 
-        result.map_err(|_| TransactionError::KeyNotFound(key.to_string()))
+        Ok(info.to_string())
     }
 
-    pub fn put(&mut self, key: String, value: Value) -> Result<String, TransactionError> {
-        let result = self.radix_tree.put(key.to_string(), value);
-        result.map_err(|_| TransactionError::InvalidKey(key))
+    pub fn put(&mut self, key: String, value: Value) -> Result<Reply, TransactionError> {
+        let old_value = match self.indexes.is_empty() {
+            true => None,
+            false => self.radix_tree.value(&key).cloned(),
+        };
+        let result = self.radix_tree.put(key.to_string(), value.clone());
+        result.map_err(|_| TransactionError::InvalidKey(key.to_string()))?;
+        self.indexes.on_put(&key, old_value.as_ref(), &value);
+        self.history
+            .record(&key, self.clock.now(), Some(value.clone()));
+        self.notify(|| KeyEvent::Put(key, value.clone()));
+
+        Ok(Reply::Written(value))
+    }
+
+    /// Deletes the key, returning the value it had.
+    pub fn delete(&mut self, key: &str) -> Result<Reply, TransactionError> {
+        let value = self.remove(key)?;
+        self.notify(|| KeyEvent::Delete(key.to_string()));
+
+        Ok(Reply::Value(value))
+    }
+
+    /// Returns a receiver for every change made from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<KeyEvent> {
+        self.events.subscribe()
+    }
+
+    /// Returns a receiver for every message published from now on.
+    pub fn subscribe_messages(&self) -> broadcast::Receiver<Message> {
+        self.messages.subscribe()
     }
 
-    pub fn delete(&mut self, key: &str) -> Result<String, TransactionError> {
-        self.exp_heap.invalidate(&key);
+    /// Sends a message to every connection with a subscription, returning how many there
+    /// are.
+    pub fn publish(&self, message: Message) -> usize {
+        self.messages.send(message).unwrap_or(0)
+    }
+
+    /// Sends an event to subscribers, only creating it if there are any.
+    fn notify(&self, event: impl FnOnce() -> KeyEvent) {
+        if self.events.receiver_count() > 0 {
+            self.events.send(event()).unwrap_or(0);
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Result<Value, TransactionError> {
+        self.exp_heap.invalidate(key);
+        if let Some(old_value) = self.radix_tree.value(key) {
+            self.indexes.on_delete(key, old_value);
+        }
         let result = self.radix_tree.delete(key.to_string());
-        result.map_err(|_| TransactionError::KeyNotFound(key.to_string()))
+        let result = result.map_err(|_| TransactionError::KeyNotFound(key.to_string()))?;
+        self.history.record(key, self.clock.now(), None);
+
+        Ok(result)
+    }
+
+    /// Returns up to `limit` previous values of the key with the time each was set, newest
+    /// first.
+    pub fn history(&self, key: &str, limit: Option<usize>) -> Result<String, TransactionError> {
+        let revisions: Vec<serde_json::Value> = self
+            .history
+            .revisions(key, limit)?
+            .into_iter()
+            .map(|revision| revision.to_json())
+            .collect();
+
+        serde_json::to_string(&revisions).map_err(|_| TransactionError::SerializationFailure)
+    }
+
+    pub fn get_as_of(&self, key: &str, timestamp: i64) -> Result<Reply, TransactionError> {
+        Ok(Reply::Value(self.history.value_at(key, timestamp)?.clone()))
+    }
+
+    /// Renames a single key, keeping its expiration.
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<String, TransactionError> {
+        let value = self
+            .radix_tree
+            .value(from)
+            .cloned()
+            .ok_or(TransactionError::KeyNotFound(from.to_string()))?;
+        let expiration = self.exp_heap.remove(from);
+
+        self.delete(from)?;
+        self.put(to.to_string(), value)?;
+        if let Some(expiration) = expiration {
+            self.exp_heap.push(
+                to.to_string(),
+                Expiration::new(to.to_string(), expiration.timestamp),
+            );
+        }
+
+        Ok(String::from("OK"))
+    }
+
+    /// Moves a subtree to a new parent key, carrying over expirations and index entries.
+    pub fn move_subtree(&mut self, from: &str, to: &str) -> Result<String, TransactionError> {
+        for (old_key, new_key) in self.radix_tree.move_subtree(from, to)? {
+            if let Some(expiration) = self.exp_heap.remove(&old_key) {
+                self.exp_heap.push(
+                    new_key.clone(),
+                    Expiration::new(new_key.clone(), expiration.timestamp),
+                );
+            }
+            if let Some(value) = self.radix_tree.value(&new_key) {
+                self.indexes.on_delete(&old_key, value);
+                self.indexes.on_put(&new_key, None, value);
+                self.history.record(&old_key, self.clock.now(), None);
+                self.history
+                    .record(&new_key, self.clock.now(), Some(value.clone()));
+                self.notify(|| KeyEvent::Delete(old_key));
+                self.notify(|| KeyEvent::Put(new_key, value.clone()));
+            }
+        }
+
+        Ok(String::from("OK"))
+    }
+
+    /// Copies a key, or every key in a subtree if `from` ends in `.*`, to `to`. With `ttl`
+    /// set, the copies expire at the same time as the originals.
+    pub fn copy(&mut self, from: &str, to: &str, ttl: bool) -> Result<String, TransactionError> {
+        let head = from.strip_suffix(".*").unwrap_or(from);
+        let entries: Vec<(String, Value)> = self
+            .radix_tree
+            .values(from)?
+            .into_iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+
+        for (key, value) in entries {
+            let new_key = format!("{}{}", to, &key[head.len()..]);
+            self.put(new_key.clone(), value)?;
+            if let Some(expiration) = self.exp_heap.get(&key).filter(|_| ttl) {
+                self.exp_heap.push(
+                    new_key.clone(),
+                    Expiration::new(new_key, expiration.timestamp),
+                );
+            }
+        }
+
+        Ok(String::from("OK"))
     }
 
     pub fn purge(&mut self) -> Result<String, TransactionError> {
         let result = self.radix_tree.purge();
-        result
+        let purged = result
             .map_err(|_| TransactionError::OperationFailure("Unable to purge data.".to_string()))?;
         self.exp_heap.clear();
+        self.indexes.clear();
+        self.history.clear();
+        // Subscribers learn of each key that is gone, as if it had been deleted.
+        for key in purged.keys() {
+            self.notify(|| KeyEvent::Delete(key.clone()));
+        }
+        Ok(String::from("OK"))
+    }
+
+    /// Declares an index on a wildcard pattern and fills it from the existing data.
+    pub fn create_index(&mut self, pattern: &str) -> Result<String, TransactionError> {
+        if self.indexes.contains(pattern) {
+            return Ok(String::from("OK"));
+        }
+
+        let mut index = Index::new(pattern)?;
+        for (key, value) in self.radix_tree.values(pattern)? {
+            index.insert(key, value);
+        }
+        self.indexes.create(pattern, index);
+
+        Ok(String::from("OK"))
+    }
+
+    pub fn drop_index(&mut self, pattern: &str) -> Result<String, TransactionError> {
+        self.indexes.drop_index(pattern)?;
         Ok(String::from("OK"))
     }
 
+    /// Looks up the parent keys whose indexed field holds the value, returning the subtree
+    /// of each.
+    pub fn find(&self, pattern: &str, value: &Value) -> Result<String, TransactionError> {
+        let parents = self.indexes.get(pattern)?.find(value);
+        self.radix_tree.subtrees(&parents)
+    }
+
+    pub fn keys(&self, pattern: &str, syntax: PatternSyntax) -> Result<String, TransactionError> {
+        self.snapshot().keys(pattern, syntax)
+    }
+
+    pub fn scan(
+        &self,
+        prefix: &str,
+        cursor: Option<&str>,
+        count: usize,
+    ) -> Result<String, TransactionError> {
+        self.snapshot().scan(prefix, cursor, count)
+    }
+
     pub fn expire_at(&mut self, expiration: Expiration) -> Result<String, TransactionError> {
         let result = self.radix_tree.get(&expiration.key);
         result.map_err(|_| TransactionError::KeyNotFound(expiration.key.clone()))?;
@@ -103,61 +444,267 @@ impl KVMap {
         Ok(String::from("OK"))
     }
 
+    /// Removes the key's expiration, if it has one.
+    pub fn persist(&mut self, key: &str) -> Result<String, TransactionError> {
+        if self.radix_tree.value(key).is_none() {
+            return Err(TransactionError::KeyNotFound(key.to_string()));
+        }
+        self.exp_heap.invalidate(key);
+
+        Ok(String::from("OK"))
+    }
+
+    /// Applies the writes of a batch in order.
+    fn apply_batch(&mut self, operations: Vec<Operation>) -> Result<String, TransactionError> {
+        for operation in operations {
+            match operation {
+                Operation::Put(key, value) => self.put(key, value).map(|_| ())?,
+                Operation::ExpireAt(expiration) => self.expire_at(expiration).map(|_| ())?,
+                Operation::Persist(key) => self.persist(&key).map(|_| ())?,
+                _ => return Err(TransactionError::InternalError),
+            }
+        }
+
+        Ok(String::from("OK"))
+    }
+
+    /// Returns whether an expiration may be due. Entries for keys that have since been
+    /// deleted or expired again are only discarded by `process_expirations`, so this can
+    /// report an expiration that turns out to have nothing to do.
+    pub fn has_due_expirations(&self) -> bool {
+        match self.exp_heap.peek_unchecked() {
+            Some(expiration) => expiration.timestamp <= self.clock.now(),
+            None => false,
+        }
+    }
+
+    /// Returns the milliseconds until the key expires, or -1 if it has no expiration.
+    pub fn ttl(&self, key: &str) -> i64 {
+        match self.exp_heap.get(key) {
+            Some(expiration) => expiration.timestamp - self.clock.now(),
+            None => -1,
+        }
+    }
+
     pub fn process_expirations(&mut self) -> Result<(), TransactionError> {
-        let now = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as i64;
+        let now = self.clock.now();
 
         while let Some(expiration) = self.exp_heap.peek() {
             if expiration.timestamp > now {
                 break;
             }
 
-            let key = expiration.key.clone();
-            self.delete(&key)?;
+            // Removing the key invalidates its entry, after which `pop` would skip it and
+            // discard the next key's expiration instead.
             self.exp_heap.pop();
+            self.remove(&expiration.key)?;
+            self.notify(|| KeyEvent::Expire(expiration.key));
         }
 
         Ok(())
     }
 
+    /// Converts an operation that depends on the current time into one that can be
+    /// replayed from the log with the same result.
+    fn resolve(&self, operation: Operation) -> Result<Operation, TransactionError> {
+        let operation = match operation {
+            // Checked here, as the duration is gone once resolved into a timestamp.
+            Operation::Expire(_, duration) if duration <= 0 => {
+                return Err(TransactionError::InvalidExpiration(duration));
+            }
+            Operation::Expire(key, duration) => Operation::ExpireAt(Expiration::new(
+                key,
+                self.clock.now().saturating_add(duration),
+            )),
+            Operation::Incr(key) => {
+                let value = match self.radix_tree.value(&key) {
+                    None => Some(1),
+                    Some(Value::Integer(value)) => value.checked_add(1),
+                    Some(_) => None,
+                };
+                match value {
+                    Some(value) => Operation::Put(key, Value::Integer(value)),
+                    None => Operation::Incr(key),
+                }
+            }
+            // Only expirations are resolved, as the other writes that can be batched do not
+            // depend on the time.
+            Operation::Batch(operations) => Operation::Batch(
+                operations
+                    .into_iter()
+                    .map(|operation| match operation {
+                        Operation::Expire(_, _) => self.resolve(operation),
+                        operation => Ok(operation),
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            operation => operation,
+        };
+        Ok(operation)
+    }
+
     pub fn validate(&self, operation: &Operation) -> Result<(), TransactionError> {
         match operation {
-            Operation::Get(key) => {
-                if let Err(_) = self.radix_tree.get(&key) {
-                    return Err(TransactionError::KeyNotFound(key.to_string()));
+            Operation::Get(key) => match self.radix_tree.get(key) {
+                Err(TransactionError::KeyNotFound(_)) => {
+                    Err(TransactionError::KeyNotFound(key.to_string()))
                 }
-                Ok(())
-            }
-            Operation::Put(key, _value) => {
-                for part in key.split(".") {
-                    if part == "*" || part == "_" {
-                        return Err(TransactionError::InvalidKey(key.to_string()));
-                    }
+                Err(error) => Err(error),
+                Ok(_) => Ok(()),
+            },
+            Operation::GetOrdered(key, _, _) => match self.radix_tree.get(key) {
+                Err(TransactionError::KeyNotFound(_)) => {
+                    Err(TransactionError::KeyNotFound(key.to_string()))
                 }
-                Ok(())
+                Err(error) => Err(error),
+                Ok(_) => Ok(()),
+            },
+            Operation::Put(key, value) => {
+                validate_key(key)?;
+                validate_value(value)
             }
             Operation::Delete(key) => {
-                if let Err(_) = self.radix_tree.get(&key) {
+                if self.radix_tree.get(key).is_err() {
                     return Err(TransactionError::KeyNotFound(key.to_string()));
                 }
                 Ok(())
             }
+            Operation::Expire(_, _) => Err(TransactionError::InternalError),
             Operation::ExpireAt(expiration) => {
-                if expiration.timestamp
-                    <= SystemTime::now()
-                        .duration_since(SystemTime::UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs() as i64
-                {
+                if self.radix_tree.get(&expiration.key).is_err() {
+                    return Err(TransactionError::KeyNotFound(expiration.key.to_string()));
+                }
+                if expiration.timestamp <= self.clock.now() {
                     return Err(TransactionError::InvalidExpiration(expiration.timestamp));
                 }
 
                 Ok(())
             }
+            Operation::Persist(key) => match self.radix_tree.value(key) {
+                Some(_) => Ok(()),
+                None => Err(TransactionError::KeyNotFound(key.to_string())),
+            },
+            Operation::Batch(operations) => {
+                if operations.is_empty() {
+                    return Err(TransactionError::MissingValue);
+                }
+
+                // Keys put earlier in the batch will exist by the time the rest is applied.
+                let mut written = HashSet::new();
+                for operation in operations {
+                    match operation {
+                        Operation::Put(key, _) => {
+                            self.validate(operation)?;
+                            written.insert(key.as_str());
+                        }
+                        Operation::ExpireAt(expiration)
+                            if written.contains(expiration.key.as_str()) =>
+                        {
+                            if expiration.timestamp <= self.clock.now() {
+                                return Err(TransactionError::InvalidExpiration(
+                                    expiration.timestamp,
+                                ));
+                            }
+                        }
+                        Operation::Persist(key) if written.contains(key.as_str()) => {}
+                        Operation::ExpireAt(_) | Operation::Persist(_) => {
+                            self.validate(operation)?
+                        }
+                        _ => {
+                            return Err(TransactionError::OperationFailure(
+                                "only PUT, EXPIRE, EXPIREAT and PERSIST can be batched".to_string(),
+                            ))
+                        }
+                    }
+                }
+                Ok(())
+            }
             Operation::Time => Ok(()),
             Operation::Purge => Ok(()),
+            Operation::Keys(_, _) => Ok(()),
+            Operation::Scan(_, _, _) => Ok(()),
+            Operation::Range(_, _, _, _) => Ok(()),
+            Operation::Query(_, _) => Ok(()),
+            Operation::Index(pattern) => Index::new(pattern).map(|_| ()),
+            Operation::DropIndex(pattern) | Operation::Find(pattern, _) => {
+                self.indexes.get(pattern).map(|_| ())
+            }
+            Operation::Aggregate(_, _, _) => Ok(()),
+            Operation::Info => Ok(()),
+            Operation::Rename(from, to) => {
+                if self.radix_tree.value(from).is_none() {
+                    return Err(TransactionError::KeyNotFound(from.to_string()));
+                }
+                if self.radix_tree.value(to).is_some() {
+                    return Err(TransactionError::KeyExists(to.to_string()));
+                }
+                validate_key(to)
+            }
+            Operation::Move(from, to) => {
+                if !self.radix_tree.contains_subtree(from) {
+                    return Err(TransactionError::KeyNotFound(from.to_string()));
+                }
+                if self.radix_tree.contains_subtree(to) {
+                    return Err(TransactionError::KeyExists(to.to_string()));
+                }
+                if to.starts_with(&format!("{}.", from)) {
+                    return Err(TransactionError::InvalidKey(to.to_string()));
+                }
+                validate_key(to)
+            }
+            Operation::Copy(from, to, _) => {
+                let head = from.strip_suffix(".*");
+                if head.unwrap_or(from).contains('*') {
+                    return Err(TransactionError::InvalidKey(from.to_string()));
+                }
+                match head {
+                    Some(head) => {
+                        if !self.radix_tree.contains_subtree(head) {
+                            return Err(TransactionError::KeyNotFound(head.to_string()));
+                        }
+                        if self.radix_tree.contains_subtree(to) {
+                            return Err(TransactionError::KeyExists(to.to_string()));
+                        }
+                        if to.starts_with(&format!("{}.", head)) {
+                            return Err(TransactionError::InvalidKey(to.to_string()));
+                        }
+                    }
+                    None => {
+                        if self.radix_tree.value(from).is_none() {
+                            return Err(TransactionError::KeyNotFound(from.to_string()));
+                        }
+                        if self.radix_tree.value(to).is_some() {
+                            return Err(TransactionError::KeyExists(to.to_string()));
+                        }
+                    }
+                }
+                validate_key(to)
+            }
+            Operation::History(key, _) | Operation::GetAsOf(key, _) => {
+                if key.contains('*') {
+                    return Err(TransactionError::InvalidKey(key.to_string()));
+                }
+                Ok(())
+            }
+            Operation::Snapshot(operation) => self.validate(operation),
+            Operation::SnapshotBegin => Ok(()),
+            Operation::SnapshotEnd => Ok(()),
+            Operation::Hello(_) => Ok(()),
+            Operation::Publish(_, _) => Ok(()),
+            Operation::Auth(_, _) | Operation::Acl(_) => Ok(()),
+            Operation::Subscribe(_)
+            | Operation::Unsubscribe(_)
+            | Operation::PSubscribe(_)
+            | Operation::PUnsubscribe(_) => Ok(()),
+            Operation::Ttl(key) => match self.radix_tree.value(key) {
+                Some(_) => Ok(()),
+                None => Err(TransactionError::KeyNotFound(key.to_string())),
+            },
+            // An increment that could be applied has already been resolved into a put.
+            Operation::Incr(key) => Err(TransactionError::OperationFailure(format!(
+                "{} does not hold an integer that can be incremented",
+                key
+            ))),
         }
     }
 
@@ -166,36 +713,158 @@ impl KVMap {
     /// # Errors
     /// Returns a `TransactionError` if the key does not exist in the map.
     ///
-    pub fn process_operation(&mut self, operation: Operation) -> Result<String, TransactionError> {
+    pub fn process_operation(&mut self, operation: Operation) -> Result<Reply, TransactionError> {
         self.process_expirations()?;
+        if operation.is_read() {
+            return self.process_read(operation);
+        }
+
+        let is_increment = matches!(operation, Operation::Incr(_));
+        let operation = self.resolve(operation)?;
         self.validate(&operation)?;
 
         {
             self.wal
                 .lock()
                 .unwrap()
-                .write(&operation)
+                .write(&operation, self.clock.now())
                 .expect("Could not write to database file.");
         }
 
-        match operation {
-            Operation::Get(key) => self.get(&key),
-            Operation::Put(key, value) => self.put(key.to_string(), value),
-            Operation::Delete(key) => self.delete(&key),
+        let status = match operation {
+            // An increment replies with the new number rather than the status of a write.
+            Operation::Put(key, value) if is_increment => {
+                self.put(key, value.clone())?;
+                return Ok(Reply::Value(value));
+            }
+            Operation::Put(key, value) => return self.put(key, value),
+            Operation::Delete(key) => return self.delete(&key),
+            Operation::Expire(_, _) => Err(TransactionError::InternalError),
             Operation::ExpireAt(expiration) => self.expire_at(expiration),
-            Operation::Time => Ok(SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_millis()
-                .to_string()),
+            Operation::Persist(key) => self.persist(&key),
+            Operation::Batch(operations) => self.apply_batch(operations),
             Operation::Purge => self.purge(),
+            Operation::Index(pattern) => self.create_index(&pattern),
+            Operation::DropIndex(pattern) => self.drop_index(&pattern),
+            Operation::Rename(from, to) => self.rename(&from, &to),
+            Operation::Move(from, to) => self.move_subtree(&from, &to),
+            Operation::Copy(from, to, ttl) => self.copy(&from, &to, ttl),
+            Operation::SnapshotBegin | Operation::SnapshotEnd => {
+                Err(TransactionError::OperationFailure(
+                    "snapshot sessions are only available on a connection".to_string(),
+                ))
+            }
+            Operation::Hello(_) => Err(TransactionError::OperationFailure(
+                "protocols are only negotiated on a connection".to_string(),
+            )),
+            Operation::Subscribe(_)
+            | Operation::Unsubscribe(_)
+            | Operation::PSubscribe(_)
+            | Operation::PUnsubscribe(_) => Err(TransactionError::OperationFailure(
+                "subscriptions are only available on a connection".to_string(),
+            )),
+            Operation::Auth(_, _) | Operation::Acl(_) => Err(TransactionError::OperationFailure(
+                "access control is only available on a connection".to_string(),
+            )),
+            // Reads are handled by `process_read` before anything is logged.
+            _ => Err(TransactionError::InternalError),
+        };
+
+        status.map(Reply::Status)
+    }
+
+    /// Process an operation that does not change the data. Expirations that are due must
+    /// already have been processed.
+    ///
+    /// # Errors
+    /// Returns an `InternalError` if the operation is not a read.
+    ///
+    pub fn process_read(&self, operation: Operation) -> Result<Reply, TransactionError> {
+        self.validate(&operation)?;
+
+        let json = match operation {
+            Operation::Get(key) => return self.get(&key),
+            Operation::GetOrdered(key, order, limit) => self.get_ordered(&key, order, limit),
+            Operation::Time => return Ok(Reply::Integer(self.clock.now())),
+            Operation::Keys(pattern, syntax) => self.keys(&pattern, syntax),
+            Operation::Scan(prefix, cursor, count) => self.scan(&prefix, cursor.as_deref(), count),
+            Operation::Range(start, end, order, limit) => self.range(&start, &end, order, limit),
+            Operation::Query(prefix, predicates) => self.query(&prefix, &predicates),
+            Operation::Find(pattern, value) => self.find(&pattern, &value),
+            Operation::Aggregate(aggregate, key, strict) => {
+                return self.aggregate(aggregate, &key, strict)
+            }
+            Operation::Info => self.info(),
+            Operation::History(key, limit) => self.history(&key, limit),
+            Operation::GetAsOf(key, timestamp) => return self.get_as_of(&key, timestamp),
+            Operation::Snapshot(operation) => return self.snapshot().read(*operation),
+            Operation::Ttl(key) => return Ok(Reply::Integer(self.ttl(&key))),
+            Operation::Publish(channel, message) => {
+                let receivers = self.publish(Message::new(&channel, &message));
+                return Ok(Reply::Integer(receivers as i64));
+            }
+            _ => Err(TransactionError::InternalError),
+        };
+
+        json.map(Reply::Json)
+    }
+
+    /// Process an operation on a shared database. Reads run in parallel under a read lock,
+    /// while writes, and reads that find expirations due, take the write lock.
+    pub fn process_shared(
+        kvmap: &RwLock<KVMap>,
+        operation: Operation,
+    ) -> Result<Reply, TransactionError> {
+        if operation.is_read() {
+            return KVMap::read_shared(kvmap, |kvmap| kvmap.process_read(operation))?;
         }
+
+        kvmap.write().unwrap().process_operation(operation)
+    }
+
+    /// Runs `read` under a read lock, first processing any expirations that are due under
+    /// the write lock.
+    pub fn read_shared<T>(
+        kvmap: &RwLock<KVMap>,
+        read: impl FnOnce(&KVMap) -> T,
+    ) -> Result<T, TransactionError> {
+        {
+            let kvmap = kvmap.read().unwrap();
+            if !kvmap.has_due_expirations() {
+                return Ok(read(&kvmap));
+            }
+        }
+
+        let mut kvmap = kvmap.write().unwrap();
+        kvmap.process_expirations()?;
+        Ok(read(&kvmap))
+    }
+}
+
+fn validate_key(key: &str) -> Result<(), TransactionError> {
+    for part in key.split('.') {
+        if part.starts_with('*') || part == "_" {
+            return Err(TransactionError::InvalidKey(key.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// The log holds one operation per line, so stored strings may not contain line breaks.
+fn validate_value(value: &Value) -> Result<(), TransactionError> {
+    match value {
+        Value::String(string) if string.contains(['\r', '\n']) => Err(
+            TransactionError::InvalidValue("containing a line break".to_string()),
+        ),
+        _ => Ok(()),
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::clock::ManualClock;
+    use crate::worker::Worker;
     use assert_json_diff::assert_json_eq;
     use serde_json::json;
 
@@ -206,7 +875,10 @@ mod test {
         map.put("key".to_string(), Value::String("value".to_string()))
             .unwrap();
 
-        assert_eq!(map.get("key"), Ok("\"value\"".to_string()));
+        assert_eq!(
+            map.get("key"),
+            Ok(Reply::Value(Value::String("value".to_string())))
+        );
 
         wal_mutex.lock().unwrap().clear().unwrap();
     }
@@ -218,7 +890,10 @@ mod test {
         map.put("key".to_string(), Value::String("value".to_string()))
             .unwrap();
 
-        assert_eq!(map.delete("key"), Ok("\"value\"".to_string()));
+        assert_eq!(
+            map.delete("key"),
+            Ok(Reply::Value(Value::String("value".to_string())))
+        );
         assert_eq!(
             map.get("key"),
             Err(TransactionError::KeyNotFound("key".to_string()))
@@ -236,7 +911,7 @@ mod test {
 
         assert_eq!(
             map.process_operation(operation),
-            Ok("\"value\"".to_string())
+            Ok(Reply::Value(Value::String("value".to_string())))
         );
     }
 
@@ -249,10 +924,13 @@ mod test {
             super::Operation::Put("key".to_string(), Value::String("value".to_string()));
         assert_eq!(
             map.process_operation(operation),
-            Ok("\"value\"".to_string())
+            Ok(Reply::Written(Value::String("value".to_string())))
         );
 
-        assert_eq!(map.get("key"), Ok("\"value\"".to_string()));
+        assert_eq!(
+            map.get("key"),
+            Ok(Reply::Value(Value::String("value".to_string())))
+        );
     }
 
     #[test]
@@ -265,7 +943,7 @@ mod test {
         let operation = super::Operation::Delete("key".to_string());
         assert_eq!(
             map.process_operation(operation),
-            Ok("\"value\"".to_string())
+            Ok(Reply::Value(Value::String("value".to_string())))
         );
         assert_eq!(
             map.get("key"),
@@ -316,7 +994,7 @@ mod test {
             }
         );
         let actual = map.process_operation(operation).unwrap();
-        let actual: serde_json::Value = serde_json::from_str(&actual).unwrap();
+        let actual: serde_json::Value = serde_json::from_str(&actual.to_string()).unwrap();
 
         assert_json_eq!(expected, actual);
     }
@@ -346,8 +1024,812 @@ mod test {
         );
 
         let actual = map.process_operation(operation).unwrap();
-        let actual: serde_json::Value = serde_json::from_str(&actual).unwrap();
+        let actual: serde_json::Value = serde_json::from_str(&actual.to_string()).unwrap();
 
         assert_json_eq!(expected, actual);
     }
+
+    #[test]
+    fn test_expire_with_manual_clock() {
+        let wal_mutex = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let mut map = super::KVMap::with_clock(wal_mutex.clone(), clock.clone());
+        map.put("key".to_string(), Value::Integer(1)).unwrap();
+
+        let operation = Operation::parse("EXPIRE key 2s".to_string()).unwrap();
+        assert_eq!(map.process_operation(operation), Ok(Reply::ok()));
+
+        clock.advance(1999);
+        map.process_expirations().unwrap();
+        assert_eq!(map.get("key"), Ok(Reply::Value(Value::Integer(1))));
+
+        clock.advance(1);
+        map.process_expirations().unwrap();
+        assert_eq!(
+            map.get("key"),
+            Err(TransactionError::KeyNotFound("key".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_process_expirations_keeps_later_expirations() {
+        let wal_mutex = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let clock = Arc::new(ManualClock::new(0));
+        let mut map = super::KVMap::with_clock(wal_mutex.clone(), clock.clone());
+        map.put("a".to_string(), Value::Integer(1)).unwrap();
+        map.put("b".to_string(), Value::Integer(2)).unwrap();
+        map.expire_at(Expiration::new("a".to_string(), 10)).unwrap();
+        map.expire_at(Expiration::new("b".to_string(), 20)).unwrap();
+
+        clock.advance(10);
+        map.process_expirations().unwrap();
+        assert_eq!(map.ttl("b"), 10);
+
+        clock.advance(10);
+        map.process_expirations().unwrap();
+        assert_eq!(
+            map.get("b"),
+            Err(TransactionError::KeyNotFound("b".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_expire_rejects_non_positive_durations() {
+        let wal_mutex = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let mut map = super::KVMap::with_clock(wal_mutex.clone(), clock.clone());
+        map.put("key".to_string(), Value::Integer(1)).unwrap();
+
+        let operation = Operation::parse("EXPIRE key -5s".to_string()).unwrap();
+        assert_eq!(
+            map.process_operation(operation),
+            Err(TransactionError::InvalidExpiration(-5000))
+        );
+        let operation = Operation::parse("EXPIRE key 0ms".to_string()).unwrap();
+        assert_eq!(
+            map.process_operation(operation),
+            Err(TransactionError::InvalidExpiration(0))
+        );
+    }
+
+    #[test]
+    fn test_expireat_rejects_past_seconds() {
+        let wal_mutex = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let clock = Arc::new(ManualClock::new(1_700_000_000_000));
+        let mut map = super::KVMap::with_clock(wal_mutex.clone(), clock.clone());
+        map.put("key".to_string(), Value::Integer(1)).unwrap();
+
+        let operation = Operation::parse("EXPIREAT key 1699999999s".to_string()).unwrap();
+        assert_eq!(
+            map.process_operation(operation),
+            Err(TransactionError::InvalidExpiration(1_699_999_999_000))
+        );
+
+        let operation = Operation::parse("EXPIREAT key 1700000001s".to_string()).unwrap();
+        assert_eq!(map.process_operation(operation), Ok(Reply::ok()));
+
+        clock.advance(1000);
+        let operation = Operation::Get("key".to_string());
+        assert_eq!(
+            map.process_operation(operation),
+            Err(TransactionError::KeyNotFound("key".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_time_uses_clock() {
+        let wal_mutex = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let clock = Arc::new(ManualClock::new(42));
+        let mut map = super::KVMap::with_clock(wal_mutex.clone(), clock.clone());
+
+        assert_eq!(
+            map.process_operation(Operation::Time),
+            Ok(Reply::Integer(42))
+        );
+    }
+
+    #[test]
+    fn test_expiration_worker_with_manual_clock() {
+        let wal_mutex = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let clock = Arc::new(ManualClock::new(0));
+        let map = Arc::new(Mutex::new(super::KVMap::with_clock(
+            wal_mutex.clone(),
+            clock.clone(),
+        )));
+
+        {
+            let mut map = map.lock().unwrap();
+            map.put("session".to_string(), Value::Integer(1)).unwrap();
+            map.expire_at(Expiration::new("session".to_string(), 7500))
+                .unwrap();
+        }
+
+        let worker_map = Arc::clone(&map);
+        let mut worker = Worker::with_clock(
+            5000,
+            move || {
+                worker_map.lock().unwrap().process_expirations().unwrap();
+            },
+            clock.clone(),
+        );
+
+        assert!(worker.run_pending());
+        clock.advance(5000);
+        assert!(worker.run_pending());
+        assert_eq!(
+            map.lock().unwrap().get("session"),
+            Ok(Reply::Value(Value::Integer(1)))
+        );
+
+        clock.advance(5000);
+        assert!(worker.run_pending());
+        assert_eq!(
+            map.lock().unwrap().get("session"),
+            Err(TransactionError::KeyNotFound("session".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_process_operation_keys() {
+        let wal_mutex = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let mut map = super::KVMap::new(wal_mutex.clone());
+        map.put("config.a".to_string(), Value::Integer(1)).unwrap();
+        map.put("config.b".to_string(), Value::Integer(2)).unwrap();
+        map.put("other".to_string(), Value::Integer(3)).unwrap();
+
+        let operation = Operation::parse("KEYS config.[ab]".to_string()).unwrap();
+        assert_eq!(
+            map.process_operation(operation),
+            Ok(Reply::Json(r#"["config.a","config.b"]"#.to_string()))
+        );
+
+        let operation = Operation::parse("KEYS config.[ab".to_string()).unwrap();
+        assert_eq!(
+            map.process_operation(operation),
+            Err(TransactionError::InvalidValue("config.[ab".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_process_operation_scan() {
+        let wal_mutex = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let mut map = super::KVMap::new(wal_mutex.clone());
+        map.put("logs.a".to_string(), Value::Integer(1)).unwrap();
+        map.put("logs.b".to_string(), Value::Integer(2)).unwrap();
+        map.put("logs.c".to_string(), Value::Integer(3)).unwrap();
+
+        let operation = Operation::parse("SCAN logs COUNT 2".to_string()).unwrap();
+        let expected = json!(
+            {
+                "cursor": "logs.b",
+                "entries": [
+                    { "key": "logs.a", "value": 1 },
+                    { "key": "logs.b", "value": 2 }
+                ]
+            }
+        );
+        let actual = map.process_operation(operation).unwrap();
+        let actual: serde_json::Value = serde_json::from_str(&actual.to_string()).unwrap();
+        assert_json_eq!(expected, actual);
+
+        let operation = Operation::parse("SCAN logs COUNT 2 CURSOR logs.b".to_string()).unwrap();
+        let expected = json!(
+            {
+                "cursor": null,
+                "entries": [
+                    { "key": "logs.c", "value": 3 }
+                ]
+            }
+        );
+        let actual = map.process_operation(operation).unwrap();
+        let actual: serde_json::Value = serde_json::from_str(&actual.to_string()).unwrap();
+        assert_json_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_process_operation_range() {
+        let wal_mutex = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let mut map = super::KVMap::new(wal_mutex.clone());
+        map.put("logs.2024-01-01".to_string(), Value::Integer(1))
+            .unwrap();
+        map.put("logs.2024-01-20".to_string(), Value::Integer(2))
+            .unwrap();
+        map.put("logs.2024-02-01".to_string(), Value::Integer(3))
+            .unwrap();
+
+        let operation = Operation::parse(
+            "RANGE logs.2024-01-01 logs.2024-02-01 ORDER DESC LIMIT 1".to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            map.process_operation(operation),
+            Ok(Reply::Json(
+                r#"[{"key":"logs.2024-01-20","value":2}]"#.to_string()
+            ))
+        );
+
+        let operation = Operation::parse("GET logs.* ORDER DESC LIMIT 2".to_string()).unwrap();
+        assert_eq!(
+            map.process_operation(operation),
+            Ok(Reply::Json(
+                r#"{"2024-02-01":3,"2024-01-20":2}"#.to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_process_operation_query() {
+        let wal_mutex = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let mut map = super::KVMap::new(wal_mutex.clone());
+        map.put("scores.a".to_string(), Value::Integer(5)).unwrap();
+        map.put("scores.b".to_string(), Value::Integer(15)).unwrap();
+        map.put("scores.c".to_string(), Value::Float(10.5)).unwrap();
+
+        let operation = Operation::parse("QUERY scores WHERE _ > 10".to_string()).unwrap();
+        assert_eq!(
+            map.process_operation(operation),
+            Ok(Reply::Json(r#"{"b":15,"c":10.5}"#.to_string()))
+        );
+    }
+
+    #[test]
+    fn test_index_tracks_writes() {
+        let wal_mutex = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let mut map = super::KVMap::new(wal_mutex.clone());
+        map.put(
+            "users.1.email".to_string(),
+            Value::String("a@b.com".to_string()),
+        )
+        .unwrap();
+        map.put(
+            "users.1.name".to_string(),
+            Value::String("Jane".to_string()),
+        )
+        .unwrap();
+        map.put(
+            "users.2.email".to_string(),
+            Value::String("c@d.com".to_string()),
+        )
+        .unwrap();
+
+        let operation = Operation::parse(r#"FIND users.*.email "a@b.com""#.to_string()).unwrap();
+        assert_eq!(
+            map.process_operation(operation),
+            Err(TransactionError::IndexNotFound("users.*.email".to_string()))
+        );
+
+        let operation = Operation::parse("INDEX users.*.email".to_string()).unwrap();
+        assert_eq!(map.process_operation(operation), Ok(Reply::ok()));
+
+        let find = || Operation::parse(r#"FIND users.*.email "a@b.com""#.to_string()).unwrap();
+        assert_eq!(
+            map.process_operation(find()),
+            Ok(Reply::Json(
+                r#"{"users.1":{"email":"a@b.com","name":"Jane"}}"#.to_string()
+            ))
+        );
+
+        map.put(
+            "users.3.email".to_string(),
+            Value::String("a@b.com".to_string()),
+        )
+        .unwrap();
+        map.put(
+            "users.1.email".to_string(),
+            Value::String("x@y.com".to_string()),
+        )
+        .unwrap();
+        assert_eq!(
+            map.process_operation(find()),
+            Ok(Reply::Json(
+                r#"{"users.3":{"email":"a@b.com"}}"#.to_string()
+            ))
+        );
+
+        map.delete("users.3.email").unwrap();
+        assert_eq!(
+            map.process_operation(find()),
+            Ok(Reply::Json("{}".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_index_restores_from_log() {
+        let wal_mutex = Arc::new(Mutex::new(
+            WriteAheadLog::new("log.index.test.txt").unwrap(),
+        ));
+        wal_mutex.lock().unwrap().clear().unwrap();
+
+        let mut map = super::KVMap::new(wal_mutex.clone());
+        for command in [
+            r#"PUT users.1.email "a@b.com""#,
+            "INDEX users.*.email",
+            r#"PUT users.2.email "a@b.com""#,
+        ] {
+            let operation = Operation::parse(command.to_string()).unwrap();
+            map.process_operation(operation).unwrap();
+        }
+
+        let mut restored = super::KVMap::new(wal_mutex.clone());
+        restored.restore().unwrap();
+        assert_eq!(
+            restored.find("users.*.email", &Value::String("a@b.com".to_string())),
+            Ok(r#"{"users.1":{"email":"a@b.com"},"users.2":{"email":"a@b.com"}}"#.to_string())
+        );
+
+        wal_mutex.lock().unwrap().clear().unwrap();
+    }
+
+    #[test]
+    fn test_process_operation_aggregate() {
+        let wal_mutex = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let mut map = super::KVMap::new(wal_mutex.clone());
+        map.put("carts.1.items.a.price".to_string(), Value::Integer(5))
+            .unwrap();
+        map.put("carts.1.items.b.price".to_string(), Value::Float(2.5))
+            .unwrap();
+        map.put("carts.1.items.b.name".to_string(), Value::Null)
+            .unwrap();
+
+        let operation = Operation::parse("SUM carts.1.items.*.price".to_string()).unwrap();
+        assert_eq!(
+            map.process_operation(operation),
+            Ok(Reply::Value(Value::Float(7.5)))
+        );
+
+        let operation = Operation::parse("COUNT carts.1.*".to_string()).unwrap();
+        assert_eq!(map.process_operation(operation), Ok(Reply::Integer(3)));
+
+        let operation = Operation::parse("MAX carts.1.* STRICT".to_string()).unwrap();
+        assert_eq!(
+            map.process_operation(operation),
+            Err(TransactionError::OperationFailure(
+                "carts.1.items.b.name is not a number".to_string()
+            ))
+        );
+
+        let operation = Operation::parse("AVG carts.2.*".to_string()).unwrap();
+        assert_eq!(
+            map.process_operation(operation),
+            Err(TransactionError::KeyNotFound("carts.2.*".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_process_operation_info() {
+        let wal_mutex = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let clock = Arc::new(ManualClock::new(0));
+        let mut map = super::KVMap::with_clock(wal_mutex.clone(), clock.clone());
+        map.put("a.b".to_string(), Value::Integer(1)).unwrap();
+        map.put("a.c".to_string(), Value::Integer(2)).unwrap();
+        map.put("d".to_string(), Value::Integer(3)).unwrap();
+        map.process_operation(Operation::Expire("d".to_string(), 10))
+            .unwrap();
+
+        let actual = map.process_operation(Operation::Info).unwrap();
+        let actual: serde_json::Value = serde_json::from_str(&actual.to_string()).unwrap();
+        assert_json_eq!(actual, json!({"keys": 3, "expires": 1, "indexes": 0}));
+
+        clock.advance(10);
+        let actual = map.process_operation(Operation::Info).unwrap();
+        let actual: serde_json::Value = serde_json::from_str(&actual.to_string()).unwrap();
+        assert_json_eq!(actual, json!({"keys": 2, "expires": 0, "indexes": 0}));
+    }
+
+    #[test]
+    fn test_process_operation_rename_and_move() {
+        let wal_mutex = Arc::new(Mutex::new(WriteAheadLog::new("log.move.test.txt").unwrap()));
+        wal_mutex.lock().unwrap().clear().unwrap();
+        let clock = Arc::new(ManualClock::new(0));
+        let mut map = super::KVMap::with_clock(wal_mutex.clone(), clock.clone());
+
+        for command in [
+            r#"PUT users.1.email "a@b.com""#,
+            "PUT users.1 1",
+            "PUT users.1.age 30",
+            "INDEX users.*.email",
+            "EXPIRE users.1.age 10ms",
+            "RENAME users.1.age users.1.years",
+            "MOVE users.1.* users.2",
+        ] {
+            let operation = Operation::parse(command.to_string()).unwrap();
+            map.process_operation(operation).unwrap();
+        }
+
+        let expected = json!({"_": 1, "email": "a@b.com", "years": 30});
+        let actual: serde_json::Value =
+            serde_json::from_str(&map.get("users.*").unwrap().to_string()).unwrap();
+        assert_json_eq!(actual, json!({"2": expected}));
+        assert_eq!(
+            map.find("users.*.email", &Value::String("a@b.com".to_string())),
+            Ok(r#"{"users.2":{"_":1,"email":"a@b.com","years":30}}"#.to_string())
+        );
+
+        let operation = Operation::parse("RENAME users.2 users.2.email".to_string()).unwrap();
+        assert_eq!(
+            map.process_operation(operation),
+            Err(TransactionError::KeyExists("users.2.email".to_string()))
+        );
+        let operation = Operation::parse("MOVE users.2.* users.2.old".to_string()).unwrap();
+        assert_eq!(
+            map.process_operation(operation),
+            Err(TransactionError::InvalidKey("users.2.old".to_string()))
+        );
+        let operation = Operation::parse("MOVE users.2.* archive.*2".to_string()).unwrap();
+        assert_eq!(
+            map.process_operation(operation),
+            Err(TransactionError::InvalidKey("archive.*2".to_string()))
+        );
+
+        let mut restored = super::KVMap::with_clock(wal_mutex.clone(), clock.clone());
+        restored.restore().unwrap();
+        let actual: serde_json::Value =
+            serde_json::from_str(&restored.get("users.*").unwrap().to_string()).unwrap();
+        assert_json_eq!(actual, json!({"2": expected}));
+
+        clock.advance(10);
+        restored.process_expirations().unwrap();
+        assert_eq!(
+            restored.get("users.2.years"),
+            Err(TransactionError::KeyNotFound("users.2.years".to_string()))
+        );
+
+        wal_mutex.lock().unwrap().clear().unwrap();
+    }
+
+    #[test]
+    fn test_restores_moves_over_expired_keys() {
+        let wal_mutex = Arc::new(Mutex::new(
+            WriteAheadLog::new("log.expired.test.txt").unwrap(),
+        ));
+        wal_mutex.lock().unwrap().clear().unwrap();
+        let clock = Arc::new(ManualClock::new(1000));
+        let mut map = super::KVMap::with_clock(wal_mutex.clone(), clock.clone());
+
+        for command in ["PUT t.a 1", "EXPIRE t.a 1s"] {
+            let operation = Operation::parse(command.to_string()).unwrap();
+            map.process_operation(operation).unwrap();
+        }
+        clock.advance(2000);
+        for command in ["PUT s.a 2", "MOVE s.* t"] {
+            let operation = Operation::parse(command.to_string()).unwrap();
+            map.process_operation(operation).unwrap();
+        }
+
+        let mut restored = super::KVMap::with_clock(wal_mutex.clone(), clock.clone());
+        restored.restore().unwrap();
+        assert_eq!(restored.get("t.a"), Ok(Reply::Value(Value::Integer(2))));
+        assert_eq!(
+            restored.get("s.a"),
+            Err(TransactionError::KeyNotFound("s.a".to_string()))
+        );
+
+        wal_mutex.lock().unwrap().clear().unwrap();
+    }
+
+    #[test]
+    fn test_process_operation_batch() {
+        let wal_mutex = Arc::new(Mutex::new(
+            WriteAheadLog::new("log.batch.kv.test.txt").unwrap(),
+        ));
+        wal_mutex.lock().unwrap().clear().unwrap();
+        let clock = Arc::new(ManualClock::new(0));
+        let mut map = super::KVMap::with_clock(wal_mutex.clone(), clock.clone());
+
+        for command in [
+            "PUT session.a 1",
+            "EXPIRE session.a 10s",
+            r#"BATCH ["PUT session.a 2", "PERSIST session.a", "PUT session.b 3", "EXPIRE session.b 5s"]"#,
+        ] {
+            let operation = Operation::parse(command.to_string()).unwrap();
+            map.process_operation(operation).unwrap();
+        }
+        assert_eq!(map.ttl("session.a"), -1);
+        assert_eq!(map.ttl("session.b"), 5000);
+
+        for (command, expected) in [
+            (
+                r#"BATCH ["PUT session.c 4", "DELETE session.a"]"#,
+                TransactionError::OperationFailure(
+                    "only PUT, EXPIRE, EXPIREAT and PERSIST can be batched".to_string(),
+                ),
+            ),
+            (
+                r#"BATCH ["PUT session.c 4", "EXPIRE session.d 5s"]"#,
+                TransactionError::KeyNotFound("session.d".to_string()),
+            ),
+        ] {
+            let operation = Operation::parse(command.to_string()).unwrap();
+            assert_eq!(map.process_operation(operation), Err(expected));
+        }
+        assert_eq!(
+            map.get("session.c"),
+            Err(TransactionError::KeyNotFound("session.c".to_string()))
+        );
+
+        let lines = wal_mutex.lock().unwrap().read_all_lines().unwrap().count();
+        assert_eq!(lines, 3);
+
+        let mut restored = super::KVMap::with_clock(wal_mutex.clone(), clock.clone());
+        restored.restore().unwrap();
+        assert_eq!(restored.ttl("session.a"), -1);
+        clock.advance(5000);
+        restored.process_expirations().unwrap();
+        assert_eq!(
+            restored.get("session.a"),
+            Ok(Reply::Value(Value::Integer(2)))
+        );
+        assert_eq!(
+            restored.get("session.b"),
+            Err(TransactionError::KeyNotFound("session.b".to_string()))
+        );
+
+        wal_mutex.lock().unwrap().clear().unwrap();
+    }
+
+    #[test]
+    fn test_process_operation_copy() {
+        let wal_mutex = Arc::new(Mutex::new(WriteAheadLog::new("log.copy.test.txt").unwrap()));
+        wal_mutex.lock().unwrap().clear().unwrap();
+        let clock = Arc::new(ManualClock::new(0));
+        let mut map = super::KVMap::with_clock(wal_mutex.clone(), clock.clone());
+
+        for command in [
+            r#"PUT tenants.template "v1""#,
+            "PUT tenants.template.limits.users 10",
+            "PUT tenants.template.trial true",
+            "EXPIRE tenants.template.trial 10ms",
+            "COPY tenants.template.* tenants.acme",
+            "COPY tenants.template.* tenants.beta TTL",
+            "COPY tenants.template.limits.users defaults.users",
+        ] {
+            let operation = Operation::parse(command.to_string()).unwrap();
+            map.process_operation(operation).unwrap();
+        }
+
+        let expected = json!({"_": "v1", "limits": {"users": 10}, "trial": true});
+        let actual: serde_json::Value =
+            serde_json::from_str(&map.get("tenants.acme.*").unwrap().to_string()).unwrap();
+        assert_json_eq!(actual, expected);
+        assert_eq!(
+            map.get("defaults.users"),
+            Ok(Reply::Value(Value::Integer(10)))
+        );
+
+        let operation = Operation::parse("COPY tenants.acme.* tenants.beta".to_string()).unwrap();
+        assert_eq!(
+            map.process_operation(operation),
+            Err(TransactionError::KeyExists("tenants.beta".to_string()))
+        );
+        let operation = Operation::parse("COPY tenants.*.trial trials".to_string()).unwrap();
+        assert_eq!(
+            map.process_operation(operation),
+            Err(TransactionError::InvalidKey("tenants.*.trial".to_string()))
+        );
+
+        let mut restored = super::KVMap::with_clock(wal_mutex.clone(), clock.clone());
+        restored.restore().unwrap();
+        clock.advance(10);
+        restored.process_expirations().unwrap();
+        assert_eq!(
+            restored.get("tenants.acme.trial"),
+            Ok(Reply::Value(Value::Boolean(true)))
+        );
+        assert_eq!(
+            restored.get("tenants.beta.trial"),
+            Err(TransactionError::KeyNotFound(
+                "tenants.beta.trial".to_string()
+            ))
+        );
+        assert_eq!(
+            restored.get("tenants.beta.limits.users"),
+            Ok(Reply::Value(Value::Integer(10)))
+        );
+
+        wal_mutex.lock().unwrap().clear().unwrap();
+    }
+
+    #[test]
+    fn test_history_restores_from_log() {
+        let wal_mutex = Arc::new(Mutex::new(
+            WriteAheadLog::new("log.history.test.txt").unwrap(),
+        ));
+        wal_mutex.lock().unwrap().clear().unwrap();
+        let clock = Arc::new(ManualClock::new(1000));
+        let mut map = super::KVMap::with_clock(wal_mutex.clone(), clock.clone());
+
+        for command in [
+            r#"PUT config.mode "fast""#,
+            r#"PUT config.mode "safe""#,
+            "DELETE config.mode",
+            r#"PUT config.mode "fast""#,
+        ] {
+            let operation = Operation::parse(command.to_string()).unwrap();
+            map.process_operation(operation).unwrap();
+            clock.advance(1000);
+        }
+
+        let operation = Operation::parse("HISTORY config.mode LIMIT 3".to_string()).unwrap();
+        let expected = json!([
+            {"timestamp": 4000, "value": "fast"},
+            {"timestamp": 3000, "deleted": true},
+            {"timestamp": 2000, "value": "safe"},
+        ]);
+        let actual: serde_json::Value =
+            serde_json::from_str(&map.process_operation(operation).unwrap().to_string()).unwrap();
+        assert_json_eq!(actual, expected);
+
+        let mut restored = super::KVMap::new(wal_mutex.clone());
+        restored.restore().unwrap();
+        let actual: serde_json::Value =
+            serde_json::from_str(&restored.history("config.mode", Some(3)).unwrap()).unwrap();
+        assert_json_eq!(actual, expected);
+
+        let operation = Operation::parse("GET config.mode ASOF 2500ms".to_string()).unwrap();
+        assert_eq!(
+            restored.process_operation(operation),
+            Ok(Reply::Value(Value::String("safe".to_string())))
+        );
+        let operation = Operation::parse("GET config.mode ASOF 3500ms".to_string()).unwrap();
+        assert_eq!(
+            restored.process_operation(operation),
+            Err(TransactionError::KeyNotFound("config.mode".to_string()))
+        );
+
+        wal_mutex.lock().unwrap().clear().unwrap();
+    }
+
+    #[test]
+    fn test_rejects_line_breaks_that_would_corrupt_the_log() {
+        let wal_mutex = Arc::new(Mutex::new(
+            WriteAheadLog::new("log.linebreak.test.txt").unwrap(),
+        ));
+        wal_mutex.lock().unwrap().clear().unwrap();
+        let mut map = super::KVMap::new(wal_mutex.clone());
+
+        let operation = Operation::Put("a".to_string(), Value::String("a\t  b".to_string()));
+        map.process_operation(operation).unwrap();
+        let operation = Operation::Put("b".to_string(), Value::String("a\nb".to_string()));
+        assert_eq!(
+            map.process_operation(operation),
+            Err(TransactionError::InvalidValue(
+                "containing a line break".to_string()
+            ))
+        );
+
+        let mut restored = super::KVMap::new(wal_mutex.clone());
+        restored.restore().unwrap();
+        assert_eq!(restored.radix_tree.value("a"), map.radix_tree.value("a"));
+        assert!(restored.radix_tree.value("b").is_none());
+    }
+
+    #[test]
+    fn test_restores_legacy_log_without_timestamps() {
+        let wal_mutex = Arc::new(Mutex::new(
+            WriteAheadLog::new("log.legacy.test.txt").unwrap(),
+        ));
+        wal_mutex.lock().unwrap().clear().unwrap();
+        std::fs::write("log.legacy.test.txt", "PUT a 1\n@5000 PUT a 2\nPUT b 3\n").unwrap();
+
+        let mut map = super::KVMap::new(wal_mutex.clone());
+        map.restore().unwrap();
+        assert_eq!(map.get_as_of("a", 0), Ok(Reply::Value(Value::Integer(1))));
+        assert_eq!(
+            map.get_as_of("a", 5000),
+            Ok(Reply::Value(Value::Integer(2)))
+        );
+        assert_eq!(
+            map.get_as_of("b", 5000),
+            Ok(Reply::Value(Value::Integer(3)))
+        );
+
+        wal_mutex.lock().unwrap().clear().unwrap();
+    }
+
+    #[test]
+    fn test_process_shared_reads_in_parallel() {
+        let wal_mutex = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let kvmap = Arc::new(RwLock::new(super::KVMap::new(wal_mutex.clone())));
+        super::KVMap::process_shared(&kvmap, Operation::Put("a".to_string(), Value::Integer(1)))
+            .unwrap();
+
+        // A reader still holding the lock must not block other reads.
+        let _reader = kvmap.read().unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let thread_kvmap = Arc::clone(&kvmap);
+        std::thread::spawn(move || {
+            let result =
+                super::KVMap::process_shared(&thread_kvmap, Operation::Get("a".to_string()));
+            sender.send(result).unwrap();
+        });
+
+        let result = receiver.recv_timeout(std::time::Duration::from_secs(5));
+        assert_eq!(result, Ok(Ok(Reply::Value(Value::Integer(1)))));
+    }
+
+    #[test]
+    fn test_process_shared_processes_due_expirations() {
+        let wal_mutex = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let clock = Arc::new(ManualClock::new(0));
+        let kvmap = RwLock::new(super::KVMap::with_clock(wal_mutex.clone(), clock.clone()));
+
+        for command in ["PUT a 1", "EXPIRE a 10ms"] {
+            let operation = Operation::parse(command.to_string()).unwrap();
+            super::KVMap::process_shared(&kvmap, operation).unwrap();
+        }
+        assert!(!kvmap.read().unwrap().has_due_expirations());
+
+        clock.advance(10);
+        assert!(kvmap.read().unwrap().has_due_expirations());
+        assert_eq!(
+            super::KVMap::process_shared(&kvmap, Operation::Get("a".to_string())),
+            Err(TransactionError::KeyNotFound("a".to_string()))
+        );
+        assert!(!kvmap.read().unwrap().has_due_expirations());
+    }
+
+    #[test]
+    fn test_process_operation_ttl_and_incr() {
+        let wal_mutex = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let clock = Arc::new(ManualClock::new(0));
+        let mut map = super::KVMap::with_clock(wal_mutex.clone(), clock.clone());
+
+        for (command, expected) in [
+            ("INCR visits", Ok(Reply::Value(Value::Integer(1)))),
+            ("INCR visits", Ok(Reply::Value(Value::Integer(2)))),
+            ("TTL visits", Ok(Reply::Integer(-1))),
+            ("EXPIRE visits 10s", Ok(Reply::ok())),
+            ("TTL visits", Ok(Reply::Integer(10000))),
+            (
+                "TTL missing",
+                Err(TransactionError::KeyNotFound("missing".to_string())),
+            ),
+            (
+                r#"PUT name "a""#,
+                Ok(Reply::Written(Value::String("a".to_string()))),
+            ),
+            (
+                "INCR name",
+                Err(TransactionError::OperationFailure(
+                    "name does not hold an integer that can be incremented".to_string(),
+                )),
+            ),
+        ] {
+            let operation = Operation::parse(command.to_string()).unwrap();
+            assert_eq!(map.process_operation(operation), expected, "{}", command);
+        }
+
+        clock.advance(4000);
+        assert_eq!(map.ttl("visits"), 6000);
+    }
+
+    #[test]
+    fn test_subscribe_receives_changes() {
+        let wal_mutex = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let clock = Arc::new(ManualClock::new(0));
+        let mut map = super::KVMap::with_clock(wal_mutex.clone(), clock.clone());
+        let mut events = map.subscribe();
+
+        for command in ["PUT a.b 1", "RENAME a.b a.c", "EXPIRE a.c 10ms"] {
+            let operation = Operation::parse(command.to_string()).unwrap();
+            map.process_operation(operation).unwrap();
+        }
+        clock.advance(10);
+        map.process_expirations().unwrap();
+        map.put("x".to_string(), Value::Integer(2)).unwrap();
+        map.purge().unwrap();
+
+        let received: Vec<KeyEvent> = std::iter::from_fn(|| events.try_recv().ok()).collect();
+        assert_eq!(
+            received,
+            vec![
+                KeyEvent::Put("a.b".to_string(), Value::Integer(1)),
+                KeyEvent::Delete("a.b".to_string()),
+                KeyEvent::Put("a.c".to_string(), Value::Integer(1)),
+                KeyEvent::Expire("a.c".to_string()),
+                KeyEvent::Put("x".to_string(), Value::Integer(2)),
+                KeyEvent::Delete("x".to_string()),
+            ]
+        );
+    }
 }
@@ -1,7 +1,19 @@
+pub mod acl;
+pub mod aggregate;
 pub mod atomicheap;
+pub mod clock;
 pub mod errors;
+pub mod events;
+pub mod history;
+pub mod index;
 pub mod kvmap;
 pub mod operation;
+pub mod pattern;
+pub mod pubsub;
+pub mod query;
 pub mod radixtree;
+pub mod resp;
+pub mod session;
+pub mod snapshot;
 pub mod wal;
 pub mod worker;
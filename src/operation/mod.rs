@@ -1,40 +1,214 @@
-use std::time::SystemTime;
+use crate::{
+    acl::AclCommand, aggregate::Aggregate, errors::TransactionError, pattern::PatternSyntax,
+    query::Predicate,
+};
 
-use crate::errors::TransactionError;
-
-use self::{expiration::Expiration, value::Value};
+use self::{expiration::Expiration, order::Order, value::Value};
 
 pub mod expiration;
+pub mod order;
+pub mod reply;
+pub mod timeunit;
 pub mod value;
 
 #[derive(Debug, PartialEq)]
 pub enum Operation {
     Get(String),
+    /// Get a subtree with its top-level entries in the given order, up to an optional limit.
+    GetOrdered(String, Order, Option<usize>),
     Put(String, Value),
     Delete(String),
+    /// Expire a key after a duration in milliseconds. Resolved into an `ExpireAt` against
+    /// the database clock before it is validated or logged.
+    Expire(String, i64),
     ExpireAt(Expiration),
+    /// Remove a key's expiration.
+    Persist(String),
     Time,
     Purge,
+    Keys(String, PatternSyntax),
+    /// Page through the values under a prefix: the prefix, the cursor to resume after and
+    /// the maximum number of entries to return.
+    Scan(String, Option<String>, usize),
+    /// Get all values with keys from the start (inclusive) to the end (exclusive).
+    Range(String, String, Order, Option<usize>),
+    /// Get the subtrees of the children of a prefix that satisfy every predicate.
+    Query(String, Vec<Predicate>),
+    /// Declare an index on a wildcard pattern such as `users.*.email`.
+    Index(String),
+    DropIndex(String),
+    /// Look up the parents of keys matching an indexed pattern that hold the value.
+    Find(String, Value),
+    /// Aggregate the values a `GET` of the key would return, optionally failing on values
+    /// that are not numbers.
+    Aggregate(Aggregate, String, bool),
+    /// Report statistics about the keyspace.
+    Info,
+    /// Rename a single key.
+    Rename(String, String),
+    /// Move the subtree at the first key, including its own value, beneath the second key.
+    Move(String, String),
+    /// Copy a key, or a subtree if the first key ends in `.*`, optionally keeping the
+    /// expirations of the copied keys.
+    Copy(String, String, bool),
+    /// Apply writes in order as a single change that is logged as one record. Only puts
+    /// and changes to expirations can be batched.
+    Batch(Vec<Operation>),
+    /// List up to an optional number of previous values of a key, newest first.
+    History(String, Option<usize>),
+    /// Get the value a key held at a timestamp in milliseconds.
+    GetAsOf(String, i64),
+    /// Run a read against a snapshot taken for just this command.
+    Snapshot(Box<Operation>),
+    /// Start reading from a snapshot for the rest of the session.
+    SnapshotBegin,
+    SnapshotEnd,
+    /// Get the milliseconds until a key expires, or -1 if it has no expiration.
+    Ttl(String),
+    /// Add one to an integer value, starting from zero if the key does not exist. Resolved
+    /// into a `Put` of the new value before it is validated or logged.
+    Incr(String),
+    /// Switch the connection to a protocol version, or report the current one.
+    Hello(Option<u8>),
+    /// Send a message to everyone subscribed to the channel.
+    Publish(String, String),
+    /// Receive messages on the channels for the rest of the session.
+    Subscribe(Vec<String>),
+    /// Stop receiving messages on the channels, or on every channel if none are given.
+    Unsubscribe(Vec<String>),
+    /// Receive messages on channels matching the glob patterns for the rest of the session.
+    PSubscribe(Vec<String>),
+    PUnsubscribe(Vec<String>),
+    /// Log the session in as a user.
+    Auth(String, String),
+    /// Manage users and their access rules.
+    Acl(AclCommand),
+}
+
+/// Parses trailing `ORDER ASC|DESC` and `LIMIT n` options, returning `None` if neither was
+/// given.
+fn parse_order_options<'a>(
+    parts: impl Iterator<Item = &'a str>,
+) -> Result<Option<(Order, Option<usize>)>, TransactionError> {
+    let mut parts = parts.peekable();
+    if parts.peek().is_none() {
+        return Ok(None);
+    }
+
+    let mut order = Order::Asc;
+    let mut limit = None;
+    while let Some(option) = parts.next() {
+        let argument = parts.next().ok_or(TransactionError::MissingValue)?;
+        match option {
+            "ORDER" => order = Order::parse(argument)?,
+            "LIMIT" => {
+                limit = Some(
+                    argument
+                        .parse::<usize>()
+                        .map_err(|_| TransactionError::InvalidValue(argument.to_string()))?,
+                )
+            }
+            other => return Err(TransactionError::InvalidValue(other.to_string())),
+        }
+    }
+
+    Ok(Some((order, limit)))
+}
+
+/// Splits off the first word of the input, returning it and everything after it.
+fn split_word(input: &str) -> (&str, &str) {
+    let input = input.trim_start();
+    input.split_at(input.find(char::is_whitespace).unwrap_or(input.len()))
+}
+
+/// Collects the remaining channel names, failing if `required` and there are none.
+fn parse_channels<'a>(
+    parts: impl Iterator<Item = &'a str>,
+    required: bool,
+) -> Result<Vec<String>, TransactionError> {
+    let channels: Vec<String> = parts.map(str::to_string).collect();
+    match required && channels.is_empty() {
+        true => Err(TransactionError::MissingKey),
+        false => Ok(channels),
+    }
 }
 
 impl Operation {
+    /// Returns whether the operation only reads stored data, so it can run on a snapshot.
+    pub fn is_snapshot_read(&self) -> bool {
+        matches!(
+            self,
+            Operation::Get(_)
+                | Operation::GetOrdered(_, _, _)
+                | Operation::Keys(_, _)
+                | Operation::Scan(_, _, _)
+                | Operation::Range(_, _, _, _)
+                | Operation::Query(_, _)
+                | Operation::Aggregate(_, _, _)
+        )
+    }
+
+    /// Returns whether the operation leaves the data unchanged, so it can run alongside
+    /// other reads.
+    pub fn is_read(&self) -> bool {
+        self.is_snapshot_read()
+            || matches!(
+                self,
+                Operation::Time
+                    | Operation::Find(_, _)
+                    | Operation::Info
+                    | Operation::History(_, _)
+                    | Operation::GetAsOf(_, _)
+                    | Operation::Snapshot(_)
+                    | Operation::Ttl(_)
+                    | Operation::Publish(_, _)
+            )
+    }
+
     pub fn parse(command: String) -> Result<Self, TransactionError> {
+        // A trailing SNAPSHOT is only an option of reads that support it. Anywhere else it
+        // is an ordinary argument, such as part of a published message.
+        if let Some(read) = command.trim_end().strip_suffix(" SNAPSHOT") {
+            match Operation::parse(read.to_string()) {
+                Ok(operation) if operation.is_snapshot_read() => {
+                    return Ok(Operation::Snapshot(Box::new(operation)));
+                }
+                _ => {}
+            }
+        }
+
         let mut parts = command.split_whitespace();
 
         match parts.next() {
             Some("GET") => {
                 let key = parts.next().ok_or(TransactionError::MissingKey)?;
-                Ok(Operation::Get(key.to_string()))
+                let mut parts = parts.peekable();
+                if parts.next_if_eq(&"ASOF").is_some() {
+                    let timestamp = parts.next().ok_or(TransactionError::MissingValue)?;
+                    let timestamp = timeunit::parse_timestamp(timestamp)
+                        .ok_or(TransactionError::InvalidValue("timestamp".to_string()))?;
+                    return Ok(Operation::GetAsOf(key.to_string(), timestamp));
+                }
+                match parse_order_options(parts)? {
+                    Some((order, limit)) => {
+                        Ok(Operation::GetOrdered(key.to_string(), order, limit))
+                    }
+                    None => Ok(Operation::Get(key.to_string())),
+                }
             }
             Some("PUT") => {
                 let key = parts.next().ok_or(TransactionError::MissingKey)?;
 
-                let value = parts.collect::<Vec<&str>>().join(" ");
+                // The value is the rest of the line as written, so that strings keep their
+                // whitespace when replayed from the log.
+                let (_, rest) = split_word(&command);
+                let (_, value) = split_word(rest);
+                let value = value.trim();
                 if value.is_empty() {
                     return Err(TransactionError::MissingValue);
                 }
 
-                let value = Value::parse(&value)?;
+                let value = Value::parse(value)?;
 
                 Ok(Operation::Put(key.to_string(), value))
             }
@@ -45,36 +219,204 @@ impl Operation {
             Some("PURGE") => Ok(Operation::Purge),
             Some("EXPIREAT") => {
                 let key = parts.next().ok_or(TransactionError::MissingKey)?;
-                let timestamp = parts
-                    .next()
-                    .ok_or(TransactionError::MissingValue)?
-                    .parse::<i64>()
-                    .map_err(|_| TransactionError::InvalidValue("timestamp".to_string()))?;
+                let timestamp = parts.next().ok_or(TransactionError::MissingValue)?;
+                let timestamp = timeunit::parse_timestamp(timestamp)
+                    .ok_or(TransactionError::InvalidValue("timestamp".to_string()))?;
 
-                return Ok(Operation::ExpireAt(Expiration::new(
+                Ok(Operation::ExpireAt(Expiration::new(
                     key.to_string(),
                     timestamp,
-                )));
+                )))
             }
             Some("EXPIRE") => {
                 let key = parts.next().ok_or(TransactionError::MissingKey)?;
-                let duration = parts
-                    .next()
-                    .ok_or(TransactionError::MissingValue)?
-                    .parse::<i64>()
-                    .map_err(|_| TransactionError::InvalidValue("duration".to_string()))?;
-                let timestamp = SystemTime::now()
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis() as i64
-                    + duration;
+                let duration = parts.next().ok_or(TransactionError::MissingValue)?;
+                let duration = timeunit::parse_duration(duration)
+                    .ok_or(TransactionError::InvalidValue("duration".to_string()))?;
 
-                Ok(Operation::ExpireAt(Expiration::new(
-                    key.to_string(),
-                    timestamp,
-                )))
+                Ok(Operation::Expire(key.to_string(), duration))
+            }
+            Some("PERSIST") => {
+                let key = parts.next().ok_or(TransactionError::MissingKey)?;
+                Ok(Operation::Persist(key.to_string()))
+            }
+            Some("BATCH") => {
+                // The commands are given as a JSON array of strings, so that each keeps
+                // its values as written.
+                let (_, commands) = split_word(&command);
+                let commands: Vec<String> = serde_json::from_str(commands)
+                    .map_err(|_| TransactionError::InvalidValue("batch".to_string()))?;
+                if commands.is_empty() {
+                    return Err(TransactionError::MissingValue);
+                }
+
+                let operations = commands.into_iter().map(Operation::parse);
+                Ok(Operation::Batch(operations.collect::<Result<_, _>>()?))
             }
             Some("TIME") => Ok(Operation::Time),
+            Some("TTL") => {
+                let key = parts.next().ok_or(TransactionError::MissingKey)?;
+                Ok(Operation::Ttl(key.to_string()))
+            }
+            Some("INCR") => {
+                let key = parts.next().ok_or(TransactionError::MissingKey)?;
+                Ok(Operation::Incr(key.to_string()))
+            }
+            Some(command @ ("RENAME" | "MOVE")) => {
+                let from = parts.next().ok_or(TransactionError::MissingKey)?;
+                let to = parts.next().ok_or(TransactionError::MissingKey)?;
+                if command == "RENAME" {
+                    return Ok(Operation::Rename(from.to_string(), to.to_string()));
+                }
+                let from = from
+                    .strip_suffix(".*")
+                    .ok_or(TransactionError::InvalidKey(from.to_string()))?;
+                Ok(Operation::Move(from.to_string(), to.to_string()))
+            }
+            Some("COPY") => {
+                let from = parts.next().ok_or(TransactionError::MissingKey)?;
+                let to = parts.next().ok_or(TransactionError::MissingKey)?;
+                let ttl = match parts.next() {
+                    None => false,
+                    Some("TTL") => true,
+                    Some(other) => return Err(TransactionError::InvalidValue(other.to_string())),
+                };
+                Ok(Operation::Copy(from.to_string(), to.to_string(), ttl))
+            }
+            Some("HISTORY") => {
+                let key = parts.next().ok_or(TransactionError::MissingKey)?;
+                let limit = match parts.next() {
+                    None => None,
+                    Some("LIMIT") => {
+                        let limit = parts.next().ok_or(TransactionError::MissingValue)?;
+                        Some(
+                            limit
+                                .parse::<usize>()
+                                .map_err(|_| TransactionError::InvalidValue(limit.to_string()))?,
+                        )
+                    }
+                    Some(other) => return Err(TransactionError::InvalidValue(other.to_string())),
+                };
+                Ok(Operation::History(key.to_string(), limit))
+            }
+            Some("SNAPSHOT") => match parts.next() {
+                Some("BEGIN") => Ok(Operation::SnapshotBegin),
+                Some("END") => Ok(Operation::SnapshotEnd),
+                Some(other) => Err(TransactionError::InvalidValue(other.to_string())),
+                None => Err(TransactionError::MissingValue),
+            },
+            Some("PUBLISH") => {
+                let channel = parts.next().ok_or(TransactionError::MissingKey)?;
+                let message = parts.collect::<Vec<&str>>().join(" ");
+                if message.is_empty() {
+                    return Err(TransactionError::MissingValue);
+                }
+                Ok(Operation::Publish(channel.to_string(), message))
+            }
+            Some("SUBSCRIBE") => Ok(Operation::Subscribe(parse_channels(parts, true)?)),
+            Some("UNSUBSCRIBE") => Ok(Operation::Unsubscribe(parse_channels(parts, false)?)),
+            Some("PSUBSCRIBE") => Ok(Operation::PSubscribe(parse_channels(parts, true)?)),
+            Some("PUNSUBSCRIBE") => Ok(Operation::PUnsubscribe(parse_channels(parts, false)?)),
+            Some("AUTH") => {
+                let user = parts.next().ok_or(TransactionError::MissingKey)?;
+                let password = parts.next().ok_or(TransactionError::MissingValue)?;
+                Ok(Operation::Auth(user.to_string(), password.to_string()))
+            }
+            Some("ACL") => Ok(Operation::Acl(AclCommand::parse(parts)?)),
+            Some("HELLO") => match parts.next() {
+                None => Ok(Operation::Hello(None)),
+                Some(version) => match version.parse() {
+                    Ok(version) => Ok(Operation::Hello(Some(version))),
+                    Err(_) => Err(TransactionError::InvalidValue(version.to_string())),
+                },
+            },
+            Some("INFO") => match parts.next() {
+                None | Some("keyspace") => Ok(Operation::Info),
+                Some(other) => Err(TransactionError::InvalidValue(other.to_string())),
+            },
+            Some("KEYS") => {
+                let pattern = parts.next().ok_or(TransactionError::MissingKey)?;
+                let syntax = match parts.next() {
+                    None => PatternSyntax::Glob,
+                    Some("REGEX") => PatternSyntax::Regex,
+                    Some(other) => return Err(TransactionError::InvalidValue(other.to_string())),
+                };
+                Ok(Operation::Keys(pattern.to_string(), syntax))
+            }
+            Some("SCAN") => {
+                let prefix = parts.next().ok_or(TransactionError::MissingKey)?;
+                let prefix = prefix.strip_suffix(".*").unwrap_or(prefix);
+
+                let mut cursor = None;
+                let mut count = 10;
+                while let Some(option) = parts.next() {
+                    let argument = parts.next().ok_or(TransactionError::MissingValue)?;
+                    match option {
+                        "CURSOR" => cursor = Some(argument.to_string()),
+                        "COUNT" => {
+                            count = argument
+                                .parse::<usize>()
+                                .ok()
+                                .filter(|count| *count > 0)
+                                .ok_or(TransactionError::InvalidValue(argument.to_string()))?
+                        }
+                        other => return Err(TransactionError::InvalidValue(other.to_string())),
+                    }
+                }
+
+                Ok(Operation::Scan(prefix.to_string(), cursor, count))
+            }
+            Some("QUERY") => {
+                let prefix = parts.next().ok_or(TransactionError::MissingKey)?;
+                let prefix = prefix.strip_suffix(".*").unwrap_or(prefix);
+                match parts.next() {
+                    Some("WHERE") => {}
+                    Some(other) => return Err(TransactionError::InvalidValue(other.to_string())),
+                    None => return Err(TransactionError::MissingValue),
+                }
+
+                let predicates = Predicate::parse_all(&parts.collect::<Vec<&str>>().join(" "))?;
+                Ok(Operation::Query(prefix.to_string(), predicates))
+            }
+            Some("INDEX") => {
+                let pattern = parts.next().ok_or(TransactionError::MissingKey)?;
+                Ok(Operation::Index(pattern.to_string()))
+            }
+            Some("DROPINDEX") => {
+                let pattern = parts.next().ok_or(TransactionError::MissingKey)?;
+                Ok(Operation::DropIndex(pattern.to_string()))
+            }
+            Some("FIND") => {
+                let pattern = parts.next().ok_or(TransactionError::MissingKey)?;
+
+                let value = parts.collect::<Vec<&str>>().join(" ");
+                if value.is_empty() {
+                    return Err(TransactionError::MissingValue);
+                }
+
+                Ok(Operation::Find(pattern.to_string(), Value::parse(&value)?))
+            }
+            Some(command @ ("COUNT" | "SUM" | "MIN" | "MAX" | "AVG")) => {
+                let aggregate = Aggregate::parse(command).unwrap();
+                let key = parts.next().ok_or(TransactionError::MissingKey)?;
+                let strict = match parts.next() {
+                    None => false,
+                    Some("STRICT") => true,
+                    Some(other) => return Err(TransactionError::InvalidValue(other.to_string())),
+                };
+                Ok(Operation::Aggregate(aggregate, key.to_string(), strict))
+            }
+            Some("RANGE") => {
+                let start = parts.next().ok_or(TransactionError::MissingKey)?;
+                let end = parts.next().ok_or(TransactionError::MissingKey)?;
+                let (order, limit) = parse_order_options(parts)?.unwrap_or((Order::Asc, None));
+                Ok(Operation::Range(
+                    start.to_string(),
+                    end.to_string(),
+                    order,
+                    limit,
+                ))
+            }
             Some(other) => Err(TransactionError::UnknownCommand(other.to_string())),
             None => Err(TransactionError::MissingCommand),
         }
@@ -85,6 +427,7 @@ impl Operation {
 mod tests {
 
     use super::*;
+    use crate::acl::CommandClass;
 
     #[test]
     fn parse_get_operation() {
@@ -215,7 +558,7 @@ mod tests {
 
     #[test]
     fn parse_expireat() {
-        let test_statement = "EXPIREAT key 1234567890";
+        let test_statement = "EXPIREAT key 1234567890ms";
         let operation = Operation::parse(test_statement.to_string());
         assert_eq!(
             operation,
@@ -224,23 +567,398 @@ mod tests {
                 1234567890
             ))),
         );
+        assert_eq!(
+            Operation::parse("EXPIREAT key 1234567890".to_string()),
+            Err(TransactionError::InvalidValue("timestamp".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_expireat_seconds() {
+        let test_statement = "EXPIREAT key 1234567890s";
+        let operation = Operation::parse(test_statement.to_string());
+        assert_eq!(
+            operation,
+            Ok(Operation::ExpireAt(Expiration::new(
+                "key".to_string(),
+                1234567890000
+            ))),
+        );
     }
 
     #[test]
     fn parse_expire() {
-        let test_statement = "EXPIRE key 100";
-        let now = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as i64;
+        let test_statement = "EXPIRE key 100ms";
         let operation = Operation::parse(test_statement.to_string());
+        assert_eq!(operation, Ok(Operation::Expire("key".to_string(), 100)));
+        assert_eq!(
+            Operation::parse("EXPIRE key 100".to_string()),
+            Err(TransactionError::InvalidValue("duration".to_string()))
+        );
+    }
 
-        match operation {
-            Ok(Operation::ExpireAt(Expiration { key, timestamp })) => {
-                assert_eq!(key, "key".to_string());
-                assert!(timestamp + now > 100);
-            }
-            _ => assert!(false),
-        }
+    #[test]
+    fn parse_expire_with_unit() {
+        let test_statement = "EXPIRE key 30s";
+        let operation = Operation::parse(test_statement.to_string());
+        assert_eq!(operation, Ok(Operation::Expire("key".to_string(), 30000)));
+    }
+
+    #[test]
+    fn parse_expire_invalid_unit() {
+        let test_statement = "EXPIRE key 30d";
+        let operation = Operation::parse(test_statement.to_string());
+        assert_eq!(
+            operation,
+            Err(TransactionError::InvalidValue("duration".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_keys() {
+        let operation = Operation::parse("KEYS users.*".to_string());
+        assert_eq!(
+            operation,
+            Ok(Operation::Keys("users.*".to_string(), PatternSyntax::Glob))
+        );
+
+        let operation = Operation::parse("KEYS users\\..* REGEX".to_string());
+        assert_eq!(
+            operation,
+            Ok(Operation::Keys(
+                "users\\..*".to_string(),
+                PatternSyntax::Regex
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_scan() {
+        let operation = Operation::parse("SCAN logs".to_string());
+        assert_eq!(operation, Ok(Operation::Scan("logs".to_string(), None, 10)));
+
+        let operation = Operation::parse("SCAN logs.* COUNT 100 CURSOR logs.5".to_string());
+        assert_eq!(
+            operation,
+            Ok(Operation::Scan(
+                "logs".to_string(),
+                Some("logs.5".to_string()),
+                100
+            ))
+        );
+
+        let operation = Operation::parse("SCAN logs COUNT 0".to_string());
+        assert_eq!(
+            operation,
+            Err(TransactionError::InvalidValue("0".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_get_ordered() {
+        let operation = Operation::parse("GET logs.* ORDER DESC LIMIT 10".to_string());
+        assert_eq!(
+            operation,
+            Ok(Operation::GetOrdered(
+                "logs.*".to_string(),
+                Order::Desc,
+                Some(10)
+            ))
+        );
+
+        let operation = Operation::parse("GET logs.* ORDER SIDEWAYS".to_string());
+        assert_eq!(
+            operation,
+            Err(TransactionError::InvalidValue("SIDEWAYS".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_range() {
+        let operation = Operation::parse("RANGE logs.2024-01-01 logs.2024-02-01".to_string());
+        assert_eq!(
+            operation,
+            Ok(Operation::Range(
+                "logs.2024-01-01".to_string(),
+                "logs.2024-02-01".to_string(),
+                Order::Asc,
+                None
+            ))
+        );
+
+        let operation = Operation::parse("RANGE logs.a".to_string());
+        assert_eq!(operation, Err(TransactionError::MissingKey));
+    }
+
+    #[test]
+    fn parse_query() {
+        let operation = Operation::parse(r#"QUERY users.* WHERE status == "active""#.to_string());
+        assert_eq!(
+            operation,
+            Ok(Operation::Query(
+                "users".to_string(),
+                Predicate::parse_all(r#"status == "active""#).unwrap()
+            ))
+        );
+
+        let operation = Operation::parse("QUERY users".to_string());
+        assert_eq!(operation, Err(TransactionError::MissingValue));
+    }
+
+    #[test]
+    fn parse_index_operations() {
+        assert_eq!(
+            Operation::parse("INDEX users.*.email".to_string()),
+            Ok(Operation::Index("users.*.email".to_string()))
+        );
+        assert_eq!(
+            Operation::parse("DROPINDEX users.*.email".to_string()),
+            Ok(Operation::DropIndex("users.*.email".to_string()))
+        );
+        assert_eq!(
+            Operation::parse(r#"FIND users.*.email "a@b.com""#.to_string()),
+            Ok(Operation::Find(
+                "users.*.email".to_string(),
+                Value::String("a@b.com".to_string())
+            ))
+        );
+        assert_eq!(
+            Operation::parse("FIND users.*.email".to_string()),
+            Err(TransactionError::MissingValue)
+        );
+    }
+
+    #[test]
+    fn parse_aggregate() {
+        assert_eq!(
+            Operation::parse("COUNT users.*".to_string()),
+            Ok(Operation::Aggregate(
+                Aggregate::Count,
+                "users.*".to_string(),
+                false
+            ))
+        );
+        assert_eq!(
+            Operation::parse("SUM carts.42.items.*.price STRICT".to_string()),
+            Ok(Operation::Aggregate(
+                Aggregate::Sum,
+                "carts.42.items.*.price".to_string(),
+                true
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_info() {
+        assert_eq!(Operation::parse("INFO".to_string()), Ok(Operation::Info));
+        assert_eq!(
+            Operation::parse("INFO keyspace".to_string()),
+            Ok(Operation::Info)
+        );
+        assert_eq!(
+            Operation::parse("INFO memory".to_string()),
+            Err(TransactionError::InvalidValue("memory".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_rename_and_move() {
+        assert_eq!(
+            Operation::parse("RENAME a.b x.y".to_string()),
+            Ok(Operation::Rename("a.b".to_string(), "x.y".to_string()))
+        );
+        assert_eq!(
+            Operation::parse("MOVE a.b.* x.y".to_string()),
+            Ok(Operation::Move("a.b".to_string(), "x.y".to_string()))
+        );
+        assert_eq!(
+            Operation::parse("MOVE a.b x.y".to_string()),
+            Err(TransactionError::InvalidKey("a.b".to_string()))
+        );
+        assert_eq!(
+            Operation::parse("RENAME a.b".to_string()),
+            Err(TransactionError::MissingKey)
+        );
+    }
+
+    #[test]
+    fn parse_copy() {
+        assert_eq!(
+            Operation::parse("COPY tenants.template.* tenants.acme".to_string()),
+            Ok(Operation::Copy(
+                "tenants.template.*".to_string(),
+                "tenants.acme".to_string(),
+                false
+            ))
+        );
+        assert_eq!(
+            Operation::parse("COPY a.b x.y TTL".to_string()),
+            Ok(Operation::Copy("a.b".to_string(), "x.y".to_string(), true))
+        );
+    }
+
+    #[test]
+    fn parse_persist_and_batch() {
+        assert_eq!(
+            Operation::parse("PERSIST session.1".to_string()),
+            Ok(Operation::Persist("session.1".to_string()))
+        );
+        assert_eq!(
+            Operation::parse(r#"BATCH ["PUT a \"x  y\"", "EXPIRE a 10s"]"#.to_string()),
+            Ok(Operation::Batch(vec![
+                Operation::Put("a".to_string(), Value::String("x  y".to_string())),
+                Operation::Expire("a".to_string(), 10000),
+            ]))
+        );
+        assert_eq!(
+            Operation::parse("BATCH PUT a 1".to_string()),
+            Err(TransactionError::InvalidValue("batch".to_string()))
+        );
+        assert_eq!(
+            Operation::parse("BATCH []".to_string()),
+            Err(TransactionError::MissingValue)
+        );
+    }
+
+    #[test]
+    fn parse_history_and_get_as_of() {
+        assert_eq!(
+            Operation::parse("HISTORY config.mode LIMIT 5".to_string()),
+            Ok(Operation::History("config.mode".to_string(), Some(5)))
+        );
+        assert_eq!(
+            Operation::parse("HISTORY config.mode".to_string()),
+            Ok(Operation::History("config.mode".to_string(), None))
+        );
+        assert_eq!(
+            Operation::parse("GET config.mode ASOF 1700000000s".to_string()),
+            Ok(Operation::GetAsOf(
+                "config.mode".to_string(),
+                1_700_000_000_000
+            ))
+        );
+        assert_eq!(
+            Operation::parse("GET config.mode ASOF".to_string()),
+            Err(TransactionError::MissingValue)
+        );
+    }
+
+    #[test]
+    fn parse_snapshot() {
+        assert_eq!(
+            Operation::parse("GET x.* SNAPSHOT\n".to_string()),
+            Ok(Operation::Snapshot(Box::new(Operation::Get(
+                "x.*".to_string()
+            ))))
+        );
+        assert_eq!(
+            Operation::parse("PUT x 1 SNAPSHOT".to_string()),
+            Err(TransactionError::InvalidValue("1 SNAPSHOT".to_string()))
+        );
+        assert_eq!(
+            Operation::parse("PUBLISH chan deploy SNAPSHOT".to_string()),
+            Ok(Operation::Publish(
+                "chan".to_string(),
+                "deploy SNAPSHOT".to_string()
+            ))
+        );
+        assert_eq!(
+            Operation::parse("SNAPSHOT BEGIN".to_string()),
+            Ok(Operation::SnapshotBegin)
+        );
+        assert_eq!(
+            Operation::parse("SNAPSHOT END".to_string()),
+            Ok(Operation::SnapshotEnd)
+        );
+    }
+
+    #[test]
+    fn parse_ttl_and_incr() {
+        assert_eq!(
+            Operation::parse("TTL session.1".to_string()),
+            Ok(Operation::Ttl("session.1".to_string()))
+        );
+        assert_eq!(
+            Operation::parse("INCR visits".to_string()),
+            Ok(Operation::Incr("visits".to_string()))
+        );
+        assert_eq!(
+            Operation::parse("INCR".to_string()),
+            Err(TransactionError::MissingKey)
+        );
+    }
+
+    #[test]
+    fn parse_hello() {
+        assert_eq!(
+            Operation::parse("HELLO".to_string()),
+            Ok(Operation::Hello(None))
+        );
+        assert_eq!(
+            Operation::parse("HELLO 2".to_string()),
+            Ok(Operation::Hello(Some(2)))
+        );
+        assert_eq!(
+            Operation::parse("HELLO two".to_string()),
+            Err(TransactionError::InvalidValue("two".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_pubsub() {
+        assert_eq!(
+            Operation::parse("PUBLISH alerts disk is full".to_string()),
+            Ok(Operation::Publish(
+                "alerts".to_string(),
+                "disk is full".to_string()
+            ))
+        );
+        assert_eq!(
+            Operation::parse("PSUBSCRIBE __keyspace__.config.* alerts".to_string()),
+            Ok(Operation::PSubscribe(vec![
+                "__keyspace__.config.*".to_string(),
+                "alerts".to_string()
+            ]))
+        );
+        assert_eq!(
+            Operation::parse("UNSUBSCRIBE".to_string()),
+            Ok(Operation::Unsubscribe(Vec::new()))
+        );
+        assert_eq!(
+            Operation::parse("SUBSCRIBE".to_string()),
+            Err(TransactionError::MissingKey)
+        );
+    }
+
+    #[test]
+    fn parse_access_control() {
+        assert_eq!(
+            Operation::parse("AUTH team-a hunter2".to_string()),
+            Ok(Operation::Auth("team-a".to_string(), "hunter2".to_string()))
+        );
+        assert_eq!(
+            Operation::parse("ACL GRANT team-a team_a read,write".to_string()),
+            Ok(Operation::Acl(AclCommand::Grant(
+                "team-a".to_string(),
+                "team_a.*".to_string(),
+                vec![CommandClass::Read, CommandClass::Write]
+            )))
+        );
+        assert_eq!(
+            Operation::parse("ACL GRANT team-a team_* read".to_string()),
+            Err(TransactionError::InvalidKey("team_*".to_string()))
+        );
+        assert_eq!(
+            Operation::parse("ACL REVOKE team-a *".to_string()),
+            Ok(Operation::Acl(AclCommand::Revoke(
+                "team-a".to_string(),
+                "*".to_string(),
+                Vec::new()
+            )))
+        );
+        assert_eq!(
+            Operation::parse("ACL GRANT team-a * everything".to_string()),
+            Err(TransactionError::InvalidValue("everything".to_string()))
+        );
     }
 }
@@ -0,0 +1,17 @@
+use crate::errors::TransactionError;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+impl Order {
+    pub fn parse(order: &str) -> Result<Self, TransactionError> {
+        match order {
+            "ASC" => Ok(Order::Asc),
+            "DESC" => Ok(Order::Desc),
+            other => Err(TransactionError::InvalidValue(other.to_string())),
+        }
+    }
+}
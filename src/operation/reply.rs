@@ -0,0 +1,77 @@
+use std::fmt;
+
+use serde_json::json;
+
+use super::value::Value;
+
+/// The result of an operation, keeping what kind of result it is so that each protocol
+/// can format it without parsing text.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Reply {
+    /// A single stored value.
+    Value(Value),
+    /// A value that was written. Text responses show the value, typed responses a status.
+    Written(Value),
+    /// A JSON document, such as a subtree or a list of keys.
+    Json(String),
+    /// A number, such as a count or a time to live.
+    Integer(i64),
+    /// The outcome of a command that has no value, such as `OK`.
+    Status(String),
+}
+
+impl Reply {
+    pub fn ok() -> Self {
+        Reply::Status(String::from("OK"))
+    }
+
+    /// Returns the name of the reply's type and its value as JSON.
+    pub fn to_json(&self) -> (&'static str, serde_json::Value) {
+        match self {
+            Reply::Value(value) => (value.type_name(), json!(value)),
+            Reply::Written(_) => ("status", json!("OK")),
+            Reply::Json(text) => match serde_json::from_str(text) {
+                Ok(serde_json::Value::Array(array)) => ("array", serde_json::Value::Array(array)),
+                Ok(value) => ("object", value),
+                Err(_) => ("status", json!(text)),
+            },
+            Reply::Integer(number) => ("integer", json!(number)),
+            Reply::Status(status) => ("status", json!(status)),
+        }
+    }
+}
+
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reply::Value(value) | Reply::Written(value) => write!(f, "{}", value),
+            Reply::Json(text) | Reply::Status(text) => write!(f, "{}", text),
+            Reply::Integer(number) => write!(f, "{}", number),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keeps_strings_as_they_are() {
+        let reply = Reply::Value(Value::String(r#"C:\temp "quoted""#.to_string()));
+
+        assert_eq!(reply.to_string(), r#""C:\temp "quoted"""#);
+        assert_eq!(reply.to_json(), ("string", json!(r#"C:\temp "quoted""#)));
+    }
+
+    #[test]
+    fn reports_writes_as_statuses() {
+        let reply = Reply::Written(Value::Float(2.0));
+
+        assert_eq!(reply.to_string(), "2");
+        assert_eq!(reply.to_json(), ("status", json!("OK")));
+        assert_eq!(
+            Reply::Value(Value::Float(2.0)).to_json(),
+            ("float", json!(2.0))
+        );
+    }
+}
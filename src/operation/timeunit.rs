@@ -0,0 +1,82 @@
+/// Units that must follow a number in time-related commands, e.g. `EXPIRE key 30s`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TimeUnit {
+    Milliseconds,
+    Seconds,
+    Minutes,
+    Hours,
+}
+
+impl TimeUnit {
+    pub fn parse(suffix: &str) -> Option<Self> {
+        match suffix {
+            "ms" => Some(TimeUnit::Milliseconds),
+            "s" => Some(TimeUnit::Seconds),
+            "m" => Some(TimeUnit::Minutes),
+            "h" => Some(TimeUnit::Hours),
+            _ => None,
+        }
+    }
+
+    pub fn as_millis(&self) -> i64 {
+        match self {
+            TimeUnit::Milliseconds => 1,
+            TimeUnit::Seconds => 1000,
+            TimeUnit::Minutes => 60 * 1000,
+            TimeUnit::Hours => 60 * 60 * 1000,
+        }
+    }
+}
+
+fn split_unit(value: &str) -> Option<(i64, TimeUnit)> {
+    let split = value
+        .char_indices()
+        .find(|(i, c)| !(c.is_ascii_digit() || (*i == 0 && *c == '-')))
+        .map(|(i, _)| i)
+        .unwrap_or(value.len());
+
+    let number = value[..split].parse::<i64>().ok()?;
+    let unit = TimeUnit::parse(&value[split..])?;
+
+    Some((number, unit))
+}
+
+/// Parses a duration such as `500ms`, `30s`, `5m` or `1h` into milliseconds.
+pub fn parse_duration(value: &str) -> Option<i64> {
+    let (number, unit) = split_unit(value)?;
+    number.checked_mul(unit.as_millis())
+}
+
+/// Parses a UNIX timestamp given in milliseconds (`ms`) or seconds (`s`) into milliseconds.
+pub fn parse_timestamp(value: &str) -> Option<i64> {
+    match split_unit(value)? {
+        (number, TimeUnit::Milliseconds) => Some(number),
+        (number, TimeUnit::Seconds) => number.checked_mul(1000),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("250"), None);
+        assert_eq!(parse_duration("250ms"), Some(250));
+        assert_eq!(parse_duration("3s"), Some(3000));
+        assert_eq!(parse_duration("2m"), Some(120000));
+        assert_eq!(parse_duration("1h"), Some(3600000));
+        assert_eq!(parse_duration("-5s"), Some(-5000));
+        assert_eq!(parse_duration("5d"), None);
+        assert_eq!(parse_duration("s"), None);
+    }
+
+    #[test]
+    fn parses_timestamps() {
+        assert_eq!(parse_timestamp("1700000000000"), None);
+        assert_eq!(parse_timestamp("1700000000000ms"), Some(1700000000000));
+        assert_eq!(parse_timestamp("1700000000s"), Some(1700000000000));
+        assert_eq!(parse_timestamp("1700000000m"), None);
+    }
+}
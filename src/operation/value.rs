@@ -1,3 +1,5 @@
+use std::fmt;
+
 use serde::{Deserialize, Serialize};
 
 use crate::errors::TransactionError;
@@ -32,13 +34,26 @@ impl Value {
         }
     }
 
-    pub fn to_string(&self) -> String {
+    /// Returns the name of the value's type, as reported by typed responses.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Integer(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Boolean(_) => "boolean",
+            Value::Null => "null",
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Value::String(string) => format!("\"{}\"", string),
-            Value::Integer(number) => number.to_string(),
-            Value::Float(number) => number.to_string(),
-            Value::Boolean(boolean) => boolean.to_string(),
-            Value::Null => "null".to_string(),
+            Value::String(string) => write!(f, "\"{}\"", string),
+            Value::Integer(number) => write!(f, "{}", number),
+            Value::Float(number) => write!(f, "{}", number),
+            Value::Boolean(boolean) => write!(f, "{}", boolean),
+            Value::Null => write!(f, "null"),
         }
     }
 }
@@ -0,0 +1,187 @@
+use regex_automata::{
+    dfa::{dense, Automaton, StartKind},
+    nfa::thompson,
+    util::{primitives::StateID, start},
+    Anchored,
+};
+
+use crate::errors::TransactionError;
+
+/// The most memory, in bytes, a pattern may take to compile. Patterns come from clients
+/// and are compiled while the map is locked, so ones whose DFA would blow up exponentially
+/// are rejected instead.
+const SIZE_LIMIT: usize = 1 << 20;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PatternSyntax {
+    /// `?` matches any single character, `*` matches any sequence of characters and
+    /// `[abc]`, `[a-z]` or `[!abc]` match a character class.
+    Glob,
+    Regex,
+}
+
+/// The state of a `Pattern` after consuming part of an input.
+#[derive(Debug, Clone, Copy)]
+pub struct PatternState(StateID);
+
+/// A glob or regular expression that must match an entire key, compiled to a DFA so that
+/// it can be fed a key one segment at a time. This allows a tree walk to stop descending
+/// as soon as no key below the current node could possibly match.
+pub struct Pattern {
+    dfa: dense::DFA<Vec<u32>>,
+}
+
+impl Pattern {
+    pub fn new(source: &str, syntax: PatternSyntax) -> Result<Self, TransactionError> {
+        let regex = match syntax {
+            PatternSyntax::Glob => glob_to_regex(source)?,
+            PatternSyntax::Regex => source.to_string(),
+        };
+
+        let dfa = dense::Builder::new()
+            .configure(
+                dense::Config::new()
+                    .start_kind(StartKind::Anchored)
+                    .determinize_size_limit(Some(SIZE_LIMIT))
+                    .dfa_size_limit(Some(SIZE_LIMIT)),
+            )
+            .thompson(thompson::Config::new().nfa_size_limit(Some(SIZE_LIMIT)))
+            .build(&format!("(?:{})$", regex))
+            .map_err(|_| TransactionError::InvalidValue(source.to_string()))?;
+
+        Ok(Pattern { dfa })
+    }
+
+    pub fn start(&self) -> PatternState {
+        let config = start::Config::new().anchored(Anchored::Yes);
+        PatternState(self.dfa.start_state(&config).unwrap())
+    }
+
+    pub fn advance(&self, state: PatternState, input: &[u8]) -> PatternState {
+        let mut id = state.0;
+        for byte in input {
+            id = self.dfa.next_state(id, *byte);
+        }
+        PatternState(id)
+    }
+
+    /// Returns true if no input starting with what has been consumed so far can match.
+    pub fn is_dead(&self, state: PatternState) -> bool {
+        self.dfa.is_dead_state(state.0)
+    }
+
+    /// Returns true if the input consumed so far is a complete match.
+    pub fn is_match(&self, state: PatternState) -> bool {
+        self.dfa.is_match_state(self.dfa.next_eoi_state(state.0))
+    }
+
+    pub fn matches(&self, input: &str) -> bool {
+        self.is_match(self.advance(self.start(), input.as_bytes()))
+    }
+}
+
+fn escape_char(c: char, output: &mut String) {
+    if "\\.+*?()|[]{}^$#&-~".contains(c) {
+        output.push('\\');
+    }
+    output.push(c);
+}
+
+fn glob_to_regex(glob: &str) -> Result<String, TransactionError> {
+    let invalid = || TransactionError::InvalidValue(glob.to_string());
+    let mut regex = String::from("(?s)");
+    let mut chars = glob.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '\\' => escape_char(chars.next().ok_or_else(invalid)?, &mut regex),
+            '[' => {
+                regex.push('[');
+                if matches!(chars.peek(), Some('!') | Some('^')) {
+                    chars.next();
+                    regex.push('^');
+                }
+                if chars.peek() == Some(&']') {
+                    chars.next();
+                    regex.push_str("\\]");
+                }
+                loop {
+                    match chars.next().ok_or_else(invalid)? {
+                        ']' => break,
+                        '-' => regex.push('-'),
+                        '\\' => escape_char(chars.next().ok_or_else(invalid)?, &mut regex),
+                        c => escape_char(c, &mut regex),
+                    }
+                }
+                regex.push(']');
+            }
+            c => escape_char(c, &mut regex),
+        }
+    }
+
+    Ok(regex)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn glob(pattern: &str) -> Pattern {
+        Pattern::new(pattern, PatternSyntax::Glob).unwrap()
+    }
+
+    #[test]
+    fn matches_glob_wildcards() {
+        assert!(glob("users.*").matches("users.1.email"));
+        assert!(!glob("users.*").matches("user"));
+        assert!(glob("users.?.email").matches("users.1.email"));
+        assert!(!glob("users.?.email").matches("users.12.email"));
+        assert!(glob("a.b").matches("a.b"));
+        assert!(!glob("a.b").matches("axb"));
+    }
+
+    #[test]
+    fn matches_glob_character_classes() {
+        assert!(glob("log.[abc]").matches("log.b"));
+        assert!(!glob("log.[abc]").matches("log.d"));
+        assert!(glob("log.[a-c]x").matches("log.cx"));
+        assert!(glob("log.[!a-c]").matches("log.d"));
+        assert!(!glob("log.[!a-c]").matches("log.a"));
+    }
+
+    #[test]
+    fn rejects_unterminated_class() {
+        assert!(Pattern::new("log.[ab", PatternSyntax::Glob).is_err());
+    }
+
+    #[test]
+    fn matches_whole_key_with_regex() {
+        let pattern = Pattern::new(r"users\.\d+\.email", PatternSyntax::Regex).unwrap();
+        assert!(pattern.matches("users.42.email"));
+        assert!(!pattern.matches("users.42.email.backup"));
+        assert!(!pattern.matches("users.abc.email"));
+    }
+
+    #[test]
+    fn rejects_patterns_too_large_to_compile() {
+        for source in [".*a.{20}", "(a{1000}){1000}"] {
+            assert_eq!(
+                Pattern::new(source, PatternSyntax::Regex).err(),
+                Some(TransactionError::InvalidValue(source.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn detects_dead_prefixes() {
+        let pattern = glob("users.*.email");
+        let state = pattern.advance(pattern.start(), b"orders");
+        assert!(pattern.is_dead(state));
+
+        let state = pattern.advance(pattern.start(), b"users.1");
+        assert!(!pattern.is_dead(state));
+        assert!(!pattern.is_match(state));
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    errors::TransactionError,
+    events::KeyEvent,
+    pattern::{Pattern, PatternSyntax},
+};
+
+/// Changes to a key are published on this channel followed by the key, such as
+/// `__keyspace__.users.42`, with the kind of change as the message.
+pub const KEYSPACE: &str = "__keyspace__";
+
+/// A message published on a channel.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Message {
+    pub channel: String,
+    pub payload: String,
+}
+
+impl Message {
+    pub fn new(channel: &str, payload: &str) -> Self {
+        Message {
+            channel: channel.to_string(),
+            payload: payload.to_string(),
+        }
+    }
+
+    pub fn from_event(event: &KeyEvent) -> Self {
+        Message {
+            channel: format!("{}.{}", KEYSPACE, event.key()),
+            payload: event.name().to_string(),
+        }
+    }
+}
+
+/// The channels and glob patterns a connection is subscribed to.
+#[derive(Default)]
+pub struct Subscriptions {
+    channels: BTreeSet<String>,
+    patterns: BTreeMap<String, Pattern>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Self {
+        Subscriptions {
+            channels: BTreeSet::new(),
+            patterns: BTreeMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn subscribe(&mut self, channels: Vec<String>) {
+        self.channels.extend(channels);
+    }
+
+    /// Unsubscribes from the channels, or from every channel if none are given.
+    pub fn unsubscribe(&mut self, channels: Vec<String>) {
+        match channels.is_empty() {
+            true => self.channels.clear(),
+            false => channels.iter().for_each(|channel| {
+                self.channels.remove(channel);
+            }),
+        }
+    }
+
+    pub fn psubscribe(&mut self, patterns: Vec<String>) -> Result<(), TransactionError> {
+        let patterns = patterns
+            .into_iter()
+            .map(|source| {
+                Pattern::new(&source, PatternSyntax::Glob).map(|pattern| (source, pattern))
+            })
+            .collect::<Result<Vec<(String, Pattern)>, TransactionError>>()?;
+        self.patterns.extend(patterns);
+        Ok(())
+    }
+
+    /// Unsubscribes from the patterns, or from every pattern if none are given.
+    pub fn punsubscribe(&mut self, patterns: Vec<String>) {
+        match patterns.is_empty() {
+            true => self.patterns.clear(),
+            false => patterns.iter().for_each(|pattern| {
+                self.patterns.remove(pattern);
+            }),
+        }
+    }
+
+    /// Returns the pattern behind each subscription the message is delivered to, or `None`
+    /// for a subscription to its exact channel.
+    pub fn matches<'a>(&'a self, message: &Message) -> Vec<Option<&'a str>> {
+        let channel = self
+            .channels
+            .contains(&message.channel)
+            .then_some(None)
+            .into_iter();
+        let patterns = self
+            .patterns
+            .iter()
+            .filter(|(_, pattern)| pattern.matches(&message.channel))
+            .map(|(source, _)| Some(source.as_str()));
+
+        channel.chain(patterns).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn matches_channels_and_patterns() {
+        let mut subscriptions = Subscriptions::new();
+        subscriptions.subscribe(vec!["__keyspace__.config.theme".to_string()]);
+        subscriptions
+            .psubscribe(vec!["__keyspace__.config.*".to_string()])
+            .unwrap();
+
+        let event = KeyEvent::Delete("config.theme".to_string());
+        let message = Message::from_event(&event);
+        assert_eq!(message, Message::new("__keyspace__.config.theme", "delete"));
+        assert_eq!(
+            subscriptions.matches(&message),
+            vec![None, Some("__keyspace__.config.*")]
+        );
+        assert!(subscriptions
+            .matches(&Message::new("__keyspace__.users.1", "put"))
+            .is_empty());
+
+        subscriptions.unsubscribe(Vec::new());
+        subscriptions.punsubscribe(vec!["__keyspace__.config.*".to_string()]);
+        assert!(subscriptions.is_empty());
+    }
+}
@@ -0,0 +1,200 @@
+use std::cmp::Ordering;
+
+use crate::{errors::TransactionError, operation::value::Value};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Comparison {
+    Eq(Value),
+    Ne(Value),
+    Lt(Value),
+    Le(Value),
+    Gt(Value),
+    Ge(Value),
+    Exists,
+    Prefix(String),
+}
+
+/// A condition on the value found at `path` relative to each child being queried. A path
+/// of `_` refers to the child's own value.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Predicate {
+    pub path: String,
+    pub comparison: Comparison,
+}
+
+impl Predicate {
+    /// Parses predicates in the form `<path> <op> <value>`, joined by `AND`.
+    pub fn parse_all(input: &str) -> Result<Vec<Self>, TransactionError> {
+        let tokens = tokenize(input)?;
+        let mut tokens = tokens.iter().map(|token| token.as_str());
+        let mut predicates = Vec::new();
+
+        loop {
+            let path = tokens.next().ok_or(TransactionError::MissingKey)?;
+            let operator = tokens.next().ok_or(TransactionError::MissingValue)?;
+
+            let comparison = match operator {
+                "EXISTS" => Comparison::Exists,
+                operator => {
+                    let value = tokens.next().ok_or(TransactionError::MissingValue)?;
+                    let value = Value::parse(value)?;
+                    match (operator, value) {
+                        ("==", value) => Comparison::Eq(value),
+                        ("!=", value) => Comparison::Ne(value),
+                        ("<", value) => Comparison::Lt(value),
+                        ("<=", value) => Comparison::Le(value),
+                        (">", value) => Comparison::Gt(value),
+                        (">=", value) => Comparison::Ge(value),
+                        ("PREFIX", Value::String(prefix)) => Comparison::Prefix(prefix),
+                        (operator, _) => {
+                            return Err(TransactionError::InvalidValue(operator.to_string()))
+                        }
+                    }
+                }
+            };
+            predicates.push(Predicate {
+                path: path.to_string(),
+                comparison,
+            });
+
+            match tokens.next() {
+                Some("AND") => continue,
+                Some(other) => return Err(TransactionError::InvalidValue(other.to_string())),
+                None => return Ok(predicates),
+            }
+        }
+    }
+
+    pub fn matches(&self, value: Option<&Value>) -> bool {
+        let value = match (value, &self.comparison) {
+            (None, _) => return false,
+            (Some(_), Comparison::Exists) => return true,
+            (Some(value), _) => value,
+        };
+
+        match &self.comparison {
+            Comparison::Eq(other) => compare(value, other) == Some(Ordering::Equal),
+            Comparison::Ne(other) => compare(value, other) != Some(Ordering::Equal),
+            Comparison::Lt(other) => compare(value, other) == Some(Ordering::Less),
+            Comparison::Le(other) => matches!(
+                compare(value, other),
+                Some(Ordering::Less | Ordering::Equal)
+            ),
+            Comparison::Gt(other) => compare(value, other) == Some(Ordering::Greater),
+            Comparison::Ge(other) => matches!(
+                compare(value, other),
+                Some(Ordering::Greater | Ordering::Equal)
+            ),
+            Comparison::Prefix(prefix) => {
+                matches!(value, Value::String(string) if string.starts_with(prefix))
+            }
+            Comparison::Exists => true,
+        }
+    }
+}
+
+/// Compares two values of the same kind. Integers and floats compare numerically, and
+/// values of different kinds are never equal or ordered.
+pub fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Integer(a), Value::Integer(b)) => Some(a.cmp(b)),
+        (Value::Integer(a), Value::Float(b)) => (*a as f64).partial_cmp(b),
+        (Value::Float(a), Value::Integer(b)) => a.partial_cmp(&(*b as f64)),
+        (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+        (Value::Boolean(a), Value::Boolean(b)) => Some(a.cmp(b)),
+        (Value::Null, Value::Null) => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+/// Splits on whitespace, keeping double-quoted strings (and their quotes) together.
+fn tokenize(input: &str) -> Result<Vec<String>, TransactionError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.peek().copied() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut token = String::new();
+        let mut quoted = false;
+        while let Some(c) = chars.peek().copied() {
+            if c.is_whitespace() && !quoted {
+                break;
+            }
+            if c == '"' {
+                quoted = !quoted;
+            }
+            token.push(c);
+            chars.next();
+        }
+        if quoted {
+            return Err(TransactionError::InvalidValue(token));
+        }
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_predicates() {
+        let predicates =
+            Predicate::parse_all(r#"status == "on hold" AND age >= 21 AND email EXISTS"#).unwrap();
+        assert_eq!(
+            predicates,
+            vec![
+                Predicate {
+                    path: "status".to_string(),
+                    comparison: Comparison::Eq(Value::String("on hold".to_string())),
+                },
+                Predicate {
+                    path: "age".to_string(),
+                    comparison: Comparison::Ge(Value::Integer(21)),
+                },
+                Predicate {
+                    path: "email".to_string(),
+                    comparison: Comparison::Exists,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_predicates() {
+        assert_eq!(
+            Predicate::parse_all("age ~ 21"),
+            Err(TransactionError::InvalidValue("~".to_string()))
+        );
+        assert_eq!(
+            Predicate::parse_all("name PREFIX 21"),
+            Err(TransactionError::InvalidValue("PREFIX".to_string()))
+        );
+        assert_eq!(
+            Predicate::parse_all("age > 21 OR"),
+            Err(TransactionError::InvalidValue("OR".to_string()))
+        );
+    }
+
+    #[test]
+    fn compares_values() {
+        let predicate = Predicate::parse_all("age > 20.5").unwrap().remove(0);
+        assert!(predicate.matches(Some(&Value::Integer(21))));
+        assert!(!predicate.matches(Some(&Value::Integer(20))));
+        assert!(!predicate.matches(Some(&Value::String("21".to_string()))));
+        assert!(!predicate.matches(None));
+
+        let predicate = Predicate::parse_all(r#"name PREFIX "Ja""#)
+            .unwrap()
+            .remove(0);
+        assert!(predicate.matches(Some(&Value::String("Jane".to_string()))));
+        assert!(!predicate.matches(Some(&Value::String("John".to_string()))));
+    }
+}
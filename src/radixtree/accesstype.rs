@@ -1,22 +1,81 @@
+use crate::errors::TransactionError;
+
+#[derive(Debug, PartialEq)]
 pub enum AccessType {
     Direct,
     FullSubtree(String),
     PartialSubtree(String, usize),
+    /// A key with `*` in a segment other than the last, such as `users.*.email`. Each `*`
+    /// matches exactly one child at that level.
+    Wildcard(Vec<String>),
 }
 
 impl AccessType {
-    pub fn parse(key: &str) -> Self {
-        let parts: Vec<&str> = key.split(".").collect();
-        let last_part = parts.last().unwrap();
+    pub fn parse(key: &str) -> Result<Self, TransactionError> {
+        let parts: Vec<&str> = key.split('.').collect();
+        let (last_part, parent_parts) = parts.split_last().unwrap();
+
+        let has_inner_wildcard = parent_parts.iter().any(|part| part.starts_with('*'));
+        if has_inner_wildcard {
+            let all_valid = parts
+                .iter()
+                .all(|part| *part == "*" || !part.starts_with('*'));
+            if !all_valid {
+                return Err(TransactionError::InvalidKey(key.to_string()));
+            }
+            return Ok(AccessType::Wildcard(
+                parts.iter().map(|part| part.to_string()).collect(),
+            ));
+        }
 
-        if last_part == &"*" {
-            let key = parts[0..parts.len() - 1].join(".");
-            AccessType::FullSubtree(key)
-        } else if last_part.chars().nth(0).unwrap() == '*' {
-            let key = parts[0..parts.len() - 1].join(".");
-            AccessType::PartialSubtree(key, last_part[1..].parse::<usize>().unwrap())
+        if *last_part == "*" {
+            Ok(AccessType::FullSubtree(parent_parts.join(".")))
+        } else if let Some(depth) = last_part.strip_prefix('*') {
+            let depth = depth
+                .parse::<usize>()
+                .map_err(|_| TransactionError::InvalidKey(key.to_string()))?;
+            Ok(AccessType::PartialSubtree(parent_parts.join("."), depth))
         } else {
-            AccessType::Direct
+            Ok(AccessType::Direct)
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_access_types() {
+        assert_eq!(AccessType::parse("a.b"), Ok(AccessType::Direct));
+        assert_eq!(
+            AccessType::parse("a.b.*"),
+            Ok(AccessType::FullSubtree("a.b".to_string()))
+        );
+        assert_eq!(
+            AccessType::parse("a.b.*2"),
+            Ok(AccessType::PartialSubtree("a.b".to_string(), 2))
+        );
+        assert_eq!(
+            AccessType::parse("a.*.c.*"),
+            Ok(AccessType::Wildcard(vec![
+                "a".to_string(),
+                "*".to_string(),
+                "c".to_string(),
+                "*".to_string()
+            ]))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_wildcards() {
+        assert_eq!(
+            AccessType::parse("a.*x"),
+            Err(TransactionError::InvalidKey("a.*x".to_string()))
+        );
+        assert_eq!(
+            AccessType::parse("a.*2.c"),
+            Err(TransactionError::InvalidKey("a.*2.c".to_string()))
+        );
+    }
+}
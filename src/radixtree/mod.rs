@@ -1,33 +1,50 @@
 mod accesstype;
 mod radixnode;
 mod recursive_map;
+mod scan_page;
 
-use crate::{errors::TransactionError, operation::value::Value};
+use crate::{
+    errors::TransactionError,
+    operation::{order::Order, reply::Reply, value::Value},
+    pattern::{Pattern, PatternState},
+    query::Predicate,
+};
 
+pub use self::scan_page::{ScanEntry, ScanPage};
 use self::{accesstype::AccessType, radixnode::RadixNode, recursive_map::RecursiveMap};
-use std::collections::HashMap;
+use im::OrdMap;
+use std::{collections::BTreeMap, ops::Bound};
 
+/// Keys are stored in persistent maps that share structure between copies, so cloning a
+/// tree is cheap and the clone is unaffected by later changes to the original.
+#[derive(Clone)]
 pub struct RadixTree {
     root: RadixNode,
-    map: HashMap<String, Value>,
+    map: OrdMap<String, Value>,
+}
+
+impl Default for RadixTree {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RadixTree {
     pub fn new() -> Self {
         RadixTree {
             root: RadixNode::new(String::from("_")),
-            map: HashMap::new(),
+            map: OrdMap::new(),
         }
     }
 
     pub fn serialize_subtree(&self, head: &RadixNode, depth: usize) -> RecursiveMap {
-        if head.children.len() == 0 {
+        if head.children.is_empty() {
             return match self.map.get(&head.key) {
                 Some(value) => RecursiveMap::Value(value.clone()),
                 None => RecursiveMap::Value(Value::Null),
             };
         }
-        let mut map: HashMap<String, RecursiveMap> = HashMap::new();
+        let mut map: BTreeMap<String, RecursiveMap> = BTreeMap::new();
         for child in head.children.keys() {
             if depth == 1 {
                 if let Some(value) = self.map.get(head.children.get(child).unwrap().key.as_str()) {
@@ -49,64 +66,419 @@ impl RadixTree {
         RecursiveMap::Map(map)
     }
 
-    pub fn get(&self, key: &str) -> Result<String, TransactionError> {
-        let access_type = AccessType::parse(key);
+    fn find_node(&self, key: &str) -> Option<&RadixNode> {
+        let mut current = &self.root;
+        for part in key.split('.') {
+            current = current.children.get(part)?;
+        }
+        Some(current)
+    }
+
+    /// Collects every node whose path matches `parts`, where a `*` part matches any single
+    /// child at that level.
+    fn match_nodes<'a>(&'a self, parts: &[String]) -> Vec<&'a RadixNode> {
+        let mut current = vec![&self.root];
+        for part in parts {
+            current = current
+                .into_iter()
+                .flat_map(|node| -> Box<dyn Iterator<Item = &RadixNode>> {
+                    if part == "*" {
+                        Box::new(node.children.values())
+                    } else {
+                        Box::new(node.children.get(part).into_iter())
+                    }
+                })
+                .collect();
+        }
+        current
+    }
+
+    fn select(&self, key: &str) -> Result<RecursiveMap, TransactionError> {
+        let access_type = AccessType::parse(key)?;
 
         match access_type {
             AccessType::Direct => match self.map.get(key) {
-                Some(value) => Ok(value.to_string()),
+                Some(value) => Ok(RecursiveMap::Value(value.clone())),
                 None => Err(TransactionError::KeyNotFound(key.to_string())),
             },
             AccessType::FullSubtree(key) => {
-                let mut current = &self.root;
-                let parts = key.split(".");
-                for part in parts {
-                    let child = current.children.get(part);
-                    match child {
-                        Some(child) => current = child,
-                        None => return Err(TransactionError::KeyNotFound(key.to_string())),
+                let current = self
+                    .find_node(&key)
+                    .ok_or(TransactionError::KeyNotFound(key.to_string()))?;
+
+                Ok(self.serialize_subtree(current, 0))
+            }
+            AccessType::PartialSubtree(key, depth) => {
+                let current = self
+                    .find_node(&key)
+                    .ok_or(TransactionError::KeyNotFound(key.to_string()))?;
+
+                Ok(self.serialize_subtree(current, depth))
+            }
+            AccessType::Wildcard(parts) => {
+                let mut map = BTreeMap::new();
+                for node in self.match_nodes(&parts) {
+                    if let Some(value) = self.map.get(&node.key) {
+                        map.insert(node.key.clone(), RecursiveMap::Value(value.clone()));
                     }
                 }
 
-                self.serialize_subtree(current, 0)
-                    .to_string()
-                    .map_err(|_| TransactionError::SerializationFailure)
+                Ok(RecursiveMap::Map(map))
+            }
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Result<Reply, TransactionError> {
+        match self.select(key)? {
+            RecursiveMap::Value(value) => Ok(Reply::Value(value)),
+            map => map
+                .to_string()
+                .map(Reply::Json)
+                .map_err(|_| TransactionError::SerializationFailure),
+        }
+    }
+
+    pub fn value(&self, key: &str) -> Option<&Value> {
+        self.map.get(key)
+    }
+
+    /// Returns every key and value that a `get` of the key would include, in key order.
+    pub fn values(&self, key: &str) -> Result<Vec<(&String, &Value)>, TransactionError> {
+        let access_type = AccessType::parse(key)?;
+
+        let (head, depth) = match access_type {
+            AccessType::Direct => {
+                return match self.map.get_key_value(key) {
+                    Some(entry) => Ok(vec![entry]),
+                    None => Err(TransactionError::KeyNotFound(key.to_string())),
+                }
+            }
+            AccessType::FullSubtree(key) => (key, None),
+            AccessType::PartialSubtree(key, depth) => (key, Some(depth)),
+            AccessType::Wildcard(parts) => {
+                let mut values: Vec<(&String, &Value)> = self
+                    .match_nodes(&parts)
+                    .into_iter()
+                    .filter_map(|node| self.map.get_key_value(&node.key))
+                    .collect();
+                values.sort_by(|a, b| a.0.cmp(b.0));
+                return Ok(values);
+            }
+        };
+
+        let head_node = self
+            .find_node(&head)
+            .ok_or(TransactionError::KeyNotFound(head.to_string()))?;
+        let mut values = Vec::new();
+        let mut stack = vec![(head_node, 0)];
+        while let Some((node, level)) = stack.pop() {
+            if let Some(entry) = self.map.get_key_value(&node.key) {
+                values.push(entry);
+            }
+            let descend = match depth {
+                Some(depth) => level < depth,
+                None => true,
+            };
+            if descend {
+                stack.extend(node.children.values().map(|child| (child, level + 1)));
+            }
+        }
+        values.sort_by(|a, b| a.0.cmp(b.0));
+
+        Ok(values)
+    }
+
+    /// Returns the number of values a `get` of the key would include. Counting a full
+    /// subtree or a single key does not visit any other nodes.
+    pub fn count(&self, key: &str) -> Result<usize, TransactionError> {
+        match AccessType::parse(key)? {
+            AccessType::Direct => match self.map.contains_key(key) {
+                true => Ok(1),
+                false => Err(TransactionError::KeyNotFound(key.to_string())),
+            },
+            AccessType::FullSubtree(head) => self
+                .find_node(&head)
+                .map(|node| node.count)
+                .ok_or(TransactionError::KeyNotFound(head)),
+            _ => Ok(self.values(key)?.len()),
+        }
+    }
+
+    pub fn contains_subtree(&self, key: &str) -> bool {
+        self.find_node(key).is_some()
+    }
+
+    /// Returns the number of values stored in the tree.
+    pub fn len(&self) -> usize {
+        self.root.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.count == 0
+    }
+
+    /// Serializes the subtree of each existing key into a map keyed by the full key.
+    pub fn subtrees(&self, keys: &[String]) -> Result<String, TransactionError> {
+        let mut map = BTreeMap::new();
+        for key in keys {
+            if let Some(node) = self.find_node(key) {
+                map.insert(key.clone(), self.serialize_subtree(node, 0));
             }
+        }
+
+        RecursiveMap::Map(map)
+            .to_string()
+            .map_err(|_| TransactionError::SerializationFailure)
+    }
+
+    /// Gets a subtree or wildcard key with its top-level entries sorted by key in the given
+    /// order, keeping at most `limit` of them. A parent value stored under `_` is always
+    /// kept first and is not counted towards the limit.
+    pub fn get_ordered(
+        &self,
+        key: &str,
+        order: Order,
+        limit: Option<usize>,
+    ) -> Result<String, TransactionError> {
+        let limit = limit.unwrap_or(usize::MAX);
+        let map = match AccessType::parse(key)? {
+            AccessType::Direct => {
+                return Err(TransactionError::OperationFailure(
+                    "ORDER and LIMIT require a wildcard key".to_string(),
+                ));
+            }
+            AccessType::FullSubtree(key) => self.ordered_children(&key, 0, order, limit)?,
             AccessType::PartialSubtree(key, depth) => {
-                let mut current = &self.root;
-                let parts = key.split(".");
-                for part in parts {
-                    let child = current.children.get(part);
-                    match child {
-                        Some(child) => current = child,
-                        None => return Err(TransactionError::KeyNotFound(key.to_string())),
-                    }
+                self.ordered_children(&key, depth, order, limit)?
+            }
+            AccessType::Wildcard(parts) => {
+                let mut values: Vec<(&String, &Value)> = self
+                    .match_nodes(&parts)
+                    .into_iter()
+                    .filter_map(|node| Some((&node.key, self.map.get(&node.key)?)))
+                    .collect();
+                values.sort_by(|a, b| a.0.cmp(b.0));
+                if let Order::Desc = order {
+                    values.reverse();
                 }
 
-                self.serialize_subtree(current, depth)
-                    .to_string()
-                    .map_err(|_| TransactionError::SerializationFailure)
+                let entries = values.into_iter().take(limit);
+                RecursiveMap::Entries(
+                    entries
+                        .map(|(key, value)| (key.clone(), RecursiveMap::Value(value.clone())))
+                        .collect(),
+                )
+            }
+        };
+
+        map.to_string()
+            .map_err(|_| TransactionError::SerializationFailure)
+    }
+
+    /// Serializes the subtree at `key` as `serialize_subtree` does, but with its children
+    /// in the given order and only the first `limit` of them serialized.
+    fn ordered_children(
+        &self,
+        key: &str,
+        depth: usize,
+        order: Order,
+        limit: usize,
+    ) -> Result<RecursiveMap, TransactionError> {
+        let head = self
+            .find_node(key)
+            .ok_or(TransactionError::KeyNotFound(key.to_string()))?;
+        if head.children.is_empty() {
+            return Ok(self.serialize_subtree(head, depth));
+        }
+
+        let mut entries = Vec::new();
+        if let Some(value) = self.map.get(&head.key) {
+            entries.push((String::from("_"), RecursiveMap::Value(value.clone())));
+        }
+
+        let children: Box<dyn Iterator<Item = (&String, &RadixNode)>> = match order {
+            Order::Asc => Box::new(head.children.iter()),
+            Order::Desc => Box::new(head.children.iter().rev()),
+        };
+        // At the last level only children with values of their own are included.
+        let children = children.filter_map(|(part, child)| {
+            let map = match depth {
+                1 => RecursiveMap::Value(self.map.get(&child.key)?.clone()),
+                _ => self.serialize_subtree(child, depth.saturating_sub(1)),
+            };
+            Some((part.clone(), map))
+        });
+        entries.extend(children.take(limit));
+
+        Ok(RecursiveMap::Entries(entries))
+    }
+
+    /// Returns the values of all keys from `start` (inclusive) to `end` (exclusive) in
+    /// lexicographic order.
+    pub fn range(
+        &self,
+        start: &str,
+        end: &str,
+        order: Order,
+        limit: Option<usize>,
+    ) -> Vec<ScanEntry> {
+        if start >= end {
+            return Vec::new();
+        }
+
+        let range = self
+            .map
+            .range::<_, str>((Bound::Included(start), Bound::Excluded(end)));
+        let range: Box<dyn Iterator<Item = (&String, &Value)>> = match order {
+            Order::Asc => Box::new(range),
+            Order::Desc => Box::new(range.rev()),
+        };
+
+        range
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|(key, value)| ScanEntry {
+                key: key.clone(),
+                value: value.clone(),
+            })
+            .collect()
+    }
+
+    /// Returns the subtree of each child of `prefix` whose values satisfy every predicate.
+    pub fn query(
+        &self,
+        prefix: &str,
+        predicates: &[Predicate],
+    ) -> Result<String, TransactionError> {
+        let head = self
+            .find_node(prefix)
+            .ok_or(TransactionError::KeyNotFound(prefix.to_string()))?;
+
+        let mut map = BTreeMap::new();
+        for (part, child) in &head.children {
+            let matches = predicates.iter().all(|predicate| {
+                let value = match predicate.path.as_str() {
+                    "_" => self.map.get(&child.key),
+                    path => self.map.get(&format!("{}.{}", child.key, path)),
+                };
+                predicate.matches(value)
+            });
+
+            if matches {
+                map.insert(part.clone(), self.serialize_subtree(child, 0));
             }
         }
+
+        RecursiveMap::Map(map)
+            .to_string()
+            .map_err(|_| TransactionError::SerializationFailure)
+    }
+
+    /// Lists every key matching the pattern in sorted order, skipping any branch whose path
+    /// can no longer lead to a match.
+    pub fn keys(&self, pattern: &Pattern) -> Vec<String> {
+        let mut keys = Vec::new();
+        for (part, child) in &self.root.children {
+            let state = pattern.advance(pattern.start(), part.as_bytes());
+            self.collect_keys(child, pattern, state, &mut keys);
+        }
+        keys.sort();
+        keys
+    }
+
+    fn collect_keys(
+        &self,
+        node: &RadixNode,
+        pattern: &Pattern,
+        state: PatternState,
+        keys: &mut Vec<String>,
+    ) {
+        if pattern.is_dead(state) {
+            return;
+        }
+        if pattern.is_match(state) && self.map.contains_key(&node.key) {
+            keys.push(node.key.clone());
+        }
+
+        let state = pattern.advance(state, b".");
+        for (part, child) in &node.children {
+            let state = pattern.advance(state, part.as_bytes());
+            self.collect_keys(child, pattern, state, keys);
+        }
+    }
+
+    /// Returns up to `count` of the values stored at or below `prefix`, in key order,
+    /// starting after the `cursor` key.
+    pub fn scan(
+        &self,
+        prefix: &str,
+        cursor: Option<&str>,
+        count: usize,
+    ) -> Result<ScanPage, TransactionError> {
+        self.find_node(prefix)
+            .ok_or(TransactionError::KeyNotFound(prefix.to_string()))?;
+
+        // The prefix itself sorts before every key beneath it, and the keys beneath it all
+        // start with `prefix.` and so are contiguous in the map.
+        let children_prefix = format!("{}.", prefix);
+        let own_value = self
+            .map
+            .get_key_value(prefix)
+            .filter(|(key, _)| match cursor {
+                Some(cursor) => key.as_str() > cursor,
+                None => true,
+            });
+        let lower = match cursor {
+            Some(cursor) if cursor >= children_prefix.as_str() => Bound::Excluded(cursor),
+            _ => Bound::Included(children_prefix.as_str()),
+        };
+        let children = self
+            .map
+            .range::<_, str>((lower, Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(&children_prefix));
+
+        // Take one extra entry to find out whether another page follows.
+        let mut entries: Vec<ScanEntry> = own_value
+            .into_iter()
+            .chain(children)
+            .take(count.saturating_add(1))
+            .map(|(key, value)| ScanEntry {
+                key: key.clone(),
+                value: value.clone(),
+            })
+            .collect();
+
+        let has_more = entries.len() > count;
+        entries.truncate(count);
+        let cursor = match has_more {
+            true => entries.last().map(|entry| entry.key.clone()),
+            false => None,
+        };
+
+        Ok(ScanPage { cursor, entries })
     }
 
     pub fn put(&mut self, key: String, value: Value) -> Result<String, TransactionError> {
-        let mut current = &mut self.root;
-        let value_result = value.to_string();
         let parts: Vec<&str> = key.split(".").collect();
-        for (i, part) in parts.iter().enumerate() {
-            if part.starts_with("*") {
-                return Err(TransactionError::InvalidKey(key));
-            }
+        if parts.iter().any(|part| part.starts_with("*")) {
+            return Err(TransactionError::InvalidKey(key));
+        }
 
-            if current.children.contains_key(*part) {
-                current = current.children.get_mut(*part).unwrap();
-            } else {
-                let child = RadixNode::new(parts[..i + 1].join("."));
-                current.children.insert(part.to_string(), child);
-                current = current.children.get_mut(*part).unwrap();
+        let is_new = !self.map.contains_key(&key);
+        let mut current = &mut self.root;
+        if is_new {
+            current.count += 1;
+        }
+        for (i, part) in parts.iter().enumerate() {
+            current = current
+                .children
+                .entry(part.to_string())
+                .or_insert_with(|| RadixNode::new(parts[..i + 1].join(".")));
+            if is_new {
+                current.count += 1;
             }
         }
+
+        let value_result = value.to_string();
         self.map.insert(key, value);
 
         Ok(value_result)
@@ -114,9 +486,10 @@ impl RadixTree {
 
     pub fn remove(
         node: &mut RadixNode,
-        map: &HashMap<String, Value>,
+        map: &OrdMap<String, Value>,
         parts: &[&str],
     ) -> Result<bool, TransactionError> {
+        node.count -= 1;
         if parts.is_empty() {
             if node.children.is_empty() && !map.contains_key(&node.key) {
                 return Ok(true);
@@ -142,7 +515,7 @@ impl RadixTree {
         Ok(false)
     }
 
-    pub fn delete(&mut self, key: String) -> Result<String, TransactionError> {
+    pub fn delete(&mut self, key: String) -> Result<Value, TransactionError> {
         if !self.map.contains_key(&key) {
             return Err(TransactionError::KeyNotFound(key.clone()));
         }
@@ -152,13 +525,92 @@ impl RadixTree {
 
         Self::remove(&mut self.root, &self.map, &parts)?;
 
-        Ok(value.to_string())
+        Ok(value)
+    }
+
+    /// Moves the node at `from`, with everything beneath it, to `to`, and returns each
+    /// moved key paired with its new key.
+    pub fn move_subtree(
+        &mut self,
+        from: &str,
+        to: &str,
+    ) -> Result<Vec<(String, String)>, TransactionError> {
+        if self.find_node(to).is_some() {
+            return Err(TransactionError::KeyExists(to.to_string()));
+        }
+        let to_parts: Vec<&str> = to.split('.').collect();
+        if to == from
+            || to.starts_with(&format!("{}.", from))
+            || to_parts.iter().any(|part| part.starts_with('*'))
+        {
+            return Err(TransactionError::InvalidKey(to.to_string()));
+        }
+
+        let from_parts: Vec<&str> = from.split('.').collect();
+        let mut node = Self::detach(&mut self.root, &from_parts)
+            .ok_or(TransactionError::KeyNotFound(from.to_string()))?;
+        let mut moved = Vec::new();
+        Self::rekey(&mut node, from, to, &mut self.map, &mut moved);
+
+        let (last, parents) = to_parts.split_last().unwrap();
+        let mut current = &mut self.root;
+        current.count += node.count;
+        for (i, part) in parents.iter().enumerate() {
+            current = current
+                .children
+                .entry(part.to_string())
+                .or_insert_with(|| RadixNode::new(to_parts[..i + 1].join(".")));
+            current.count += node.count;
+        }
+        current.children.insert(last.to_string(), node);
+        moved.sort();
+
+        Ok(moved)
+    }
+
+    /// Removes the node at the end of `parts` from beneath `node`, pruning ancestors that
+    /// are left without values.
+    fn detach(node: &mut RadixNode, parts: &[&str]) -> Option<RadixNode> {
+        let (part, remaining_parts) = parts.split_first()?;
+        let detached = if remaining_parts.is_empty() {
+            node.children.remove(*part)?
+        } else {
+            let child = node.children.get_mut(*part)?;
+            let detached = Self::detach(child, remaining_parts)?;
+            if child.count == 0 {
+                node.children.remove(*part);
+            }
+            detached
+        };
+        node.count -= detached.count;
+
+        Some(detached)
     }
 
-    pub fn purge(&mut self) -> Result<(), TransactionError> {
-        self.map = HashMap::new();
+    fn rekey(
+        node: &mut RadixNode,
+        from: &str,
+        to: &str,
+        map: &mut OrdMap<String, Value>,
+        moved: &mut Vec<(String, String)>,
+    ) {
+        let key = format!("{}{}", to, &node.key[from.len()..]);
+        if let Some(value) = map.remove(&node.key) {
+            map.insert(key.clone(), value);
+            moved.push((node.key.clone(), key.clone()));
+        }
+        node.key = key;
+        let parts: Vec<String> = node.children.keys().cloned().collect();
+        for part in parts {
+            let child = node.children.get_mut(&part).unwrap();
+            Self::rekey(child, from, to, map, moved);
+        }
+    }
+
+    /// Removes every key, returning the values the tree held.
+    pub fn purge(&mut self) -> Result<OrdMap<String, Value>, TransactionError> {
         self.root = RadixNode::new("_".to_string());
-        Ok(())
+        Ok(std::mem::take(&mut self.map))
     }
 }
 
@@ -168,6 +620,7 @@ mod test {
     use serde_json::json;
 
     use super::*;
+    use crate::pattern::PatternSyntax;
 
     #[test]
     fn puts_and_gets_single_value() {
@@ -176,7 +629,10 @@ mod test {
             .put("key".to_string(), Value::String("value".to_string()))
             .unwrap();
 
-        assert_eq!(radix.get("key").unwrap(), "\"value\"".to_string());
+        assert_eq!(
+            radix.get("key"),
+            Ok(Reply::Value(Value::String("value".to_string())))
+        );
     }
 
     #[test]
@@ -189,7 +645,10 @@ mod test {
             )
             .unwrap();
 
-        assert_eq!(radix.get("key.abc.def").unwrap(), "\"value\"".to_string());
+        assert_eq!(
+            radix.get("key.abc.def"),
+            Ok(Reply::Value(Value::String("value".to_string())))
+        );
     }
 
     #[test]
@@ -214,7 +673,7 @@ mod test {
         );
 
         let actual = radix.get("key.*").unwrap();
-        let actual = serde_json::from_str::<serde_json::Value>(&actual).unwrap();
+        let actual = serde_json::from_str::<serde_json::Value>(&actual.to_string()).unwrap();
 
         assert_json_eq!(actual, expected);
     }
@@ -243,7 +702,7 @@ mod test {
         );
 
         let actual = radix.get("key.*").unwrap();
-        let actual = serde_json::from_str::<serde_json::Value>(&actual).unwrap();
+        let actual = serde_json::from_str::<serde_json::Value>(&actual.to_string()).unwrap();
 
         assert_json_eq!(actual, expected);
     }
@@ -269,7 +728,7 @@ mod test {
         );
 
         let actual = radix.get("key.*1").unwrap();
-        let actual = serde_json::from_str::<serde_json::Value>(&actual).unwrap();
+        let actual = serde_json::from_str::<serde_json::Value>(&actual.to_string()).unwrap();
 
         assert_json_eq!(actual, expected);
     }
@@ -305,11 +764,71 @@ mod test {
         );
 
         let actual = radix.get("a.*").unwrap();
-        let actual = serde_json::from_str::<serde_json::Value>(&actual).unwrap();
+        let actual = serde_json::from_str::<serde_json::Value>(&actual.to_string()).unwrap();
 
         assert_json_eq!(actual, expected);
     }
 
+    #[test]
+    fn maintains_subtree_counts() {
+        let mut radix = RadixTree::new();
+        radix.put("a.b".to_string(), Value::Integer(1)).unwrap();
+        radix.put("a.b.c".to_string(), Value::Integer(2)).unwrap();
+        radix.put("a.d".to_string(), Value::Integer(3)).unwrap();
+        radix.put("a.d".to_string(), Value::Integer(4)).unwrap();
+        radix.put("e".to_string(), Value::Integer(5)).unwrap();
+        assert!(radix.put("a.*".to_string(), Value::Integer(6)).is_err());
+
+        assert_eq!(radix.len(), 4);
+        assert_eq!(radix.count("a.*"), Ok(3));
+        assert_eq!(radix.count("a.b.*"), Ok(2));
+        assert_eq!(radix.count("a.*1"), Ok(2));
+        assert_eq!(radix.count("a.d"), Ok(1));
+
+        radix.delete("a.b".to_string()).unwrap();
+        radix.delete("a.d".to_string()).unwrap();
+        assert_eq!(radix.len(), 2);
+        assert_eq!(radix.count("a.*"), Ok(1));
+        assert_eq!(
+            radix.count("a.d.*"),
+            Err(TransactionError::KeyNotFound("a.d".to_string()))
+        );
+    }
+
+    #[test]
+    fn moves_subtrees() {
+        let mut radix = RadixTree::new();
+        radix.put("a.b".to_string(), Value::Integer(1)).unwrap();
+        radix.put("a.b.c".to_string(), Value::Integer(2)).unwrap();
+        radix.put("x.z".to_string(), Value::Integer(3)).unwrap();
+
+        assert_eq!(
+            radix.move_subtree("a.b", "x.y"),
+            Ok(vec![
+                ("a.b".to_string(), "x.y".to_string()),
+                ("a.b.c".to_string(), "x.y.c".to_string()),
+            ])
+        );
+        assert_eq!(radix.get("x.y.c"), Ok(Reply::Value(Value::Integer(2))));
+        assert_eq!(radix.count("x.*"), Ok(3));
+        assert!(radix.get("a.*").is_err());
+        assert_eq!(
+            radix
+                .keys(&Pattern::new("*", PatternSyntax::Glob).unwrap())
+                .len(),
+            3
+        );
+
+        assert_eq!(
+            radix.move_subtree("x.y", "x.z"),
+            Err(TransactionError::KeyExists("x.z".to_string()))
+        );
+        assert_eq!(
+            radix.move_subtree("x.y", "x.y.d"),
+            Err(TransactionError::InvalidKey("x.y.d".to_string()))
+        );
+    }
+
     #[test]
     fn deletes_not_found_key_in_subtree_path() {
         let mut radix = RadixTree::new();
@@ -323,4 +842,271 @@ mod test {
             Err(TransactionError::KeyNotFound("a.b".to_string()))
         );
     }
+
+    #[test]
+    fn gets_wildcard_in_middle_of_key() {
+        let mut radix = RadixTree::new();
+        radix
+            .put(
+                "users.1.email".to_string(),
+                Value::String("a@b.com".to_string()),
+            )
+            .unwrap();
+        radix
+            .put(
+                "users.2.email".to_string(),
+                Value::String("c@d.com".to_string()),
+            )
+            .unwrap();
+        radix
+            .put(
+                "users.2.name".to_string(),
+                Value::String("Jane".to_string()),
+            )
+            .unwrap();
+        radix
+            .put(
+                "users.3.name".to_string(),
+                Value::String("John".to_string()),
+            )
+            .unwrap();
+
+        let expected = json!(
+            {
+                "users.1.email": "a@b.com",
+                "users.2.email": "c@d.com"
+            }
+        );
+
+        let actual = radix.get("users.*.email").unwrap();
+        let actual = serde_json::from_str::<serde_json::Value>(&actual.to_string()).unwrap();
+
+        assert_json_eq!(actual, expected);
+    }
+
+    #[test]
+    fn gets_multiple_wildcards_in_key() {
+        let mut radix = RadixTree::new();
+        radix
+            .put("orders.1.items.a.sku".to_string(), Value::Integer(100))
+            .unwrap();
+        radix
+            .put("orders.1.items.b.sku".to_string(), Value::Integer(200))
+            .unwrap();
+        radix
+            .put("orders.2.items.a.sku".to_string(), Value::Integer(300))
+            .unwrap();
+        radix
+            .put("orders.2.items.a.qty".to_string(), Value::Integer(1))
+            .unwrap();
+
+        let expected = json!(
+            {
+                "orders.1.items.a.sku": 100,
+                "orders.1.items.b.sku": 200,
+                "orders.2.items.a.sku": 300
+            }
+        );
+
+        let actual = radix.get("orders.*.items.*.sku").unwrap();
+        let actual = serde_json::from_str::<serde_json::Value>(&actual.to_string()).unwrap();
+
+        assert_json_eq!(actual, expected);
+    }
+
+    #[test]
+    fn gets_empty_map_for_unmatched_wildcard() {
+        let mut radix = RadixTree::new();
+        radix
+            .put(
+                "users.1.name".to_string(),
+                Value::String("Jane".to_string()),
+            )
+            .unwrap();
+
+        assert_eq!(
+            radix.get("users.*.email"),
+            Ok(Reply::Json("{}".to_string()))
+        );
+    }
+
+    #[test]
+    fn lists_keys_matching_pattern() {
+        let mut radix = RadixTree::new();
+        radix.put("users.1.email".to_string(), Value::Null).unwrap();
+        radix.put("users.2.email".to_string(), Value::Null).unwrap();
+        radix.put("users.2.name".to_string(), Value::Null).unwrap();
+        radix
+            .put("users.10.email".to_string(), Value::Null)
+            .unwrap();
+        radix.put("orders.1".to_string(), Value::Null).unwrap();
+
+        let pattern = Pattern::new("users.?.email", PatternSyntax::Glob).unwrap();
+        assert_eq!(
+            radix.keys(&pattern),
+            vec!["users.1.email".to_string(), "users.2.email".to_string()]
+        );
+
+        let pattern = Pattern::new("users.*", PatternSyntax::Glob).unwrap();
+        assert_eq!(radix.keys(&pattern).len(), 4);
+
+        let pattern = Pattern::new(r"users\.\d{2}\..*", PatternSyntax::Regex).unwrap();
+        assert_eq!(radix.keys(&pattern), vec!["users.10.email".to_string()]);
+    }
+
+    #[test]
+    fn scans_subtree_in_pages() {
+        let mut radix = RadixTree::new();
+        for i in 0..5 {
+            radix.put(format!("logs.{}", i), Value::Integer(i)).unwrap();
+        }
+        radix.put("other".to_string(), Value::Null).unwrap();
+
+        let page = radix.scan("logs", None, 2).unwrap();
+        assert_eq!(page.cursor, Some("logs.1".to_string()));
+        assert_eq!(
+            page.entries,
+            vec![
+                ScanEntry {
+                    key: "logs.0".to_string(),
+                    value: Value::Integer(0)
+                },
+                ScanEntry {
+                    key: "logs.1".to_string(),
+                    value: Value::Integer(1)
+                }
+            ]
+        );
+
+        radix.delete("logs.2".to_string()).unwrap();
+        radix.put("logs.0.a".to_string(), Value::Null).unwrap();
+
+        let page = radix.scan("logs", Some("logs.1"), 2).unwrap();
+        assert_eq!(page.cursor, None);
+        let keys: Vec<&str> = page.entries.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(keys, vec!["logs.3", "logs.4"]);
+
+        let page = radix.scan("logs", Some("logs.4"), 2).unwrap();
+        assert_eq!(page.cursor, None);
+        assert!(page.entries.is_empty());
+
+        let page = radix.scan("logs", None, usize::MAX).unwrap();
+        assert_eq!(page.cursor, None);
+        assert_eq!(page.entries.len(), 5);
+    }
+
+    #[test]
+    fn gets_children_in_order() {
+        let mut radix = RadixTree::new();
+        radix.put("logs".to_string(), Value::Null).unwrap();
+        for day in ["2024-01-03", "2024-01-01", "2024-01-02"] {
+            radix
+                .put(format!("logs.{}", day), Value::String(day.to_string()))
+                .unwrap();
+        }
+
+        assert_eq!(
+            radix.get("logs.*").unwrap().to_string(),
+            r#"{"2024-01-01":"2024-01-01","2024-01-02":"2024-01-02","2024-01-03":"2024-01-03","_":null}"#
+        );
+        assert_eq!(
+            radix.get_ordered("logs.*", Order::Desc, Some(2)).unwrap(),
+            r#"{"_":null,"2024-01-03":"2024-01-03","2024-01-02":"2024-01-02"}"#
+        );
+        assert_eq!(
+            radix.get_ordered("logs.*1", Order::Desc, Some(1)).unwrap(),
+            r#"{"_":null,"2024-01-03":"2024-01-03"}"#
+        );
+        assert_eq!(
+            radix.get_ordered("*.*", Order::Desc, Some(2)).unwrap(),
+            r#"{"logs.2024-01-03":"2024-01-03","logs.2024-01-02":"2024-01-02"}"#
+        );
+        assert!(radix
+            .get_ordered("logs.2024-01-01", Order::Asc, None)
+            .is_err());
+    }
+
+    #[test]
+    fn gets_lexicographic_range() {
+        let mut radix = RadixTree::new();
+        for day in ["2023-12-31", "2024-01-01", "2024-01-15", "2024-02-01"] {
+            radix
+                .put(format!("logs.{}", day), Value::String(day.to_string()))
+                .unwrap();
+        }
+
+        let keys = |entries: Vec<ScanEntry>| -> Vec<String> {
+            entries.into_iter().map(|entry| entry.key).collect()
+        };
+
+        assert_eq!(
+            keys(radix.range("logs.2024-01-01", "logs.2024-02-01", Order::Asc, None)),
+            vec!["logs.2024-01-01", "logs.2024-01-15"]
+        );
+        assert_eq!(
+            keys(radix.range("logs.2024", "logs.2025", Order::Desc, Some(2))),
+            vec!["logs.2024-02-01", "logs.2024-01-15"]
+        );
+        assert!(radix.range("logs.b", "logs.a", Order::Asc, None).is_empty());
+    }
+
+    #[test]
+    fn queries_children_by_nested_field() {
+        let mut radix = RadixTree::new();
+        let users = [
+            ("1", "active", 30),
+            ("2", "banned", 40),
+            ("3", "active", 18),
+        ];
+        for (id, status, age) in users {
+            radix
+                .put(
+                    format!("users.{}.status", id),
+                    Value::String(status.to_string()),
+                )
+                .unwrap();
+            radix
+                .put(format!("users.{}.age", id), Value::Integer(age))
+                .unwrap();
+        }
+
+        let predicates = Predicate::parse_all(r#"status == "active" AND age >= 21"#).unwrap();
+        let expected = json!(
+            {
+                "1": {
+                    "status": "active",
+                    "age": 30
+                }
+            }
+        );
+
+        let actual = radix.query("users", &predicates).unwrap();
+        let actual = serde_json::from_str::<serde_json::Value>(&actual).unwrap();
+
+        assert_json_eq!(actual, expected);
+    }
+
+    #[test]
+    fn collects_values_for_each_access_type() {
+        let mut radix = RadixTree::new();
+        radix.put("a".to_string(), Value::Integer(1)).unwrap();
+        radix.put("a.b".to_string(), Value::Integer(2)).unwrap();
+        radix.put("a.b.c".to_string(), Value::Integer(3)).unwrap();
+        radix.put("a.d.c".to_string(), Value::Integer(4)).unwrap();
+
+        let keys = |key: &str| -> Vec<String> {
+            radix
+                .values(key)
+                .unwrap()
+                .into_iter()
+                .map(|(key, _)| key.clone())
+                .collect()
+        };
+
+        assert_eq!(keys("a.b"), vec!["a.b"]);
+        assert_eq!(keys("a.*"), vec!["a", "a.b", "a.b.c", "a.d.c"]);
+        assert_eq!(keys("a.*1"), vec!["a", "a.b"]);
+        assert_eq!(keys("a.*.c"), vec!["a.b.c", "a.d.c"]);
+        assert!(radix.values("x.*").is_err());
+    }
 }
@@ -1,17 +1,20 @@
+use im::OrdMap;
 use serde::Serialize;
-use std::collections::HashMap;
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone)]
 pub struct RadixNode {
-    pub children: HashMap<String, RadixNode>,
+    pub children: OrdMap<String, RadixNode>,
     pub key: String,
+    /// The number of values stored at this node and beneath it.
+    pub count: usize,
 }
 
 impl RadixNode {
     pub fn new(key: String) -> Self {
         RadixNode {
-            children: HashMap::new(),
+            children: OrdMap::new(),
             key,
+            count: 0,
         }
     }
 }
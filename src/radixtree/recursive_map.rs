@@ -1,19 +1,47 @@
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
+use std::collections::BTreeMap;
 
 use crate::operation::value::Value;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Deserialize)]
 #[serde(untagged)]
 pub enum RecursiveMap {
-    Map(HashMap<String, RecursiveMap>),
+    Map(BTreeMap<String, RecursiveMap>),
     Value(Value),
+    /// A map whose entries serialize in the given order rather than sorted by key.
+    Entries(Vec<(String, RecursiveMap)>),
+}
+
+impl Serialize for RecursiveMap {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            RecursiveMap::Map(map) => map.serialize(serializer),
+            RecursiveMap::Value(value) => value.serialize(serializer),
+            RecursiveMap::Entries(entries) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (key, value) in entries {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+impl Default for RecursiveMap {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RecursiveMap {
     pub fn new() -> Self {
-        RecursiveMap::Map(HashMap::new())
+        RecursiveMap::Map(BTreeMap::new())
     }
+
     pub fn to_string(&self) -> Result<String, serde_json::Error> {
         serde_json::to_string(self)
     }
@@ -31,7 +59,7 @@ mod test {
 
     #[test]
     fn properly_serializes_to_json() {
-        let mut cupboard_contents = HashMap::new();
+        let mut cupboard_contents = BTreeMap::new();
         cupboard_contents.insert(
             String::from("cups"),
             RecursiveMap::Value(Value::String("3".to_string())),
@@ -41,7 +69,7 @@ mod test {
             RecursiveMap::Value(Value::String("4".to_string())),
         );
 
-        let mut refrigerator_contents = HashMap::new();
+        let mut refrigerator_contents = BTreeMap::new();
         refrigerator_contents.insert(
             String::from("milk"),
             RecursiveMap::Value(Value::String("1".to_string())),
@@ -51,7 +79,7 @@ mod test {
             RecursiveMap::Value(Value::String("12".to_string())),
         );
 
-        let mut kitchen_contents = HashMap::new();
+        let mut kitchen_contents = BTreeMap::new();
         kitchen_contents.insert(
             String::from("cupboard"),
             RecursiveMap::Map(cupboard_contents),
@@ -61,7 +89,7 @@ mod test {
             RecursiveMap::Map(refrigerator_contents),
         );
 
-        let mut house_contents = HashMap::new();
+        let mut house_contents = BTreeMap::new();
         house_contents.insert(String::from("kitchen"), RecursiveMap::Map(kitchen_contents));
 
         let expected = json!(
@@ -0,0 +1,24 @@
+use serde::Serialize;
+
+use crate::operation::value::Value;
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ScanEntry {
+    pub key: String,
+    pub value: Value,
+}
+
+/// One page of a `SCAN`. The `cursor` is the last key in the page, and passing it back
+/// resumes the scan with the keys that sort after it. It is `None` once the scan is
+/// complete.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ScanPage {
+    pub cursor: Option<String>,
+    pub entries: Vec<ScanEntry>,
+}
+
+impl ScanPage {
+    pub fn to_string(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+}
@@ -0,0 +1,459 @@
+use std::sync::{Arc, RwLock};
+
+use crate::{
+    acl::AccessControl,
+    errors::TransactionError,
+    kvmap::KVMap,
+    operation::{reply::Reply, value::Value, Operation},
+    pattern::PatternSyntax,
+    session::Session,
+};
+
+pub mod reader;
+pub mod value;
+
+pub use reader::read_command;
+pub use value::RespValue;
+
+/// A client speaking the Redis protocol. Redis commands are translated into operations
+/// and their results into Redis replies.
+pub struct RespConnection {
+    session: Session,
+    version: u8,
+}
+
+impl Default for RespConnection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RespConnection {
+    pub fn new() -> Self {
+        RespConnection {
+            session: Session::new(),
+            version: 2,
+        }
+    }
+
+    /// Creates a connection that must follow the users and rules of `access`.
+    pub fn with_access(access: Arc<AccessControl>) -> Self {
+        RespConnection {
+            session: Session::with_access(access),
+            version: 2,
+        }
+    }
+
+    /// The protocol version replies should be encoded with, as negotiated by `HELLO`.
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn execute(&mut self, kvmap: &RwLock<KVMap>, arguments: Vec<String>) -> RespValue {
+        let mut arguments = arguments.into_iter();
+        let command = match arguments.next() {
+            Some(command) => command.to_ascii_uppercase(),
+            None => return RespValue::Error("ERR no command given".to_string()),
+        };
+        let arguments: Vec<String> = arguments.collect();
+
+        let reply = match command.as_str() {
+            "PING" => self.ping(arguments),
+            "HELLO" => self.hello(kvmap, arguments),
+            "AUTH" => match arguments.as_slice() {
+                [user, password] => self.auth(kvmap, user, password),
+                _ => Err(wrong_arguments(&command)),
+            },
+            "COMMAND" => Ok(RespValue::Array(Vec::new())),
+            "GET" => match arguments.as_slice() {
+                [key] => self.get(kvmap, key),
+                _ => Err(wrong_arguments(&command)),
+            },
+            "SET" => self.set(kvmap, &command, arguments),
+            "DEL" if !arguments.is_empty() => self.delete(kvmap, arguments),
+            "EXPIRE" => match arguments.as_slice() {
+                [key, seconds] => self.expire(kvmap, key, seconds),
+                _ => Err(wrong_arguments(&command)),
+            },
+            "TTL" => match arguments.as_slice() {
+                [key] => self.ttl(kvmap, key),
+                _ => Err(wrong_arguments(&command)),
+            },
+            "INCR" => match arguments.as_slice() {
+                [key] => self.incr(kvmap, key),
+                _ => Err(wrong_arguments(&command)),
+            },
+            "KEYS" => match arguments.as_slice() {
+                [pattern] => self.keys(kvmap, pattern),
+                _ => Err(wrong_arguments(&command)),
+            },
+            "MGET" if !arguments.is_empty() => arguments
+                .iter()
+                .map(|key| self.get(kvmap, key))
+                .collect::<Result<Vec<RespValue>, String>>()
+                .map(RespValue::Array),
+            "DEL" | "MGET" => Err(wrong_arguments(&command)),
+            _ => Err(format!("ERR unknown command '{}'", command.to_lowercase())),
+        };
+
+        reply.unwrap_or_else(RespValue::Error)
+    }
+
+    fn process(
+        &mut self,
+        kvmap: &RwLock<KVMap>,
+        operation: Operation,
+    ) -> Result<Reply, TransactionError> {
+        self.session.process(kvmap, operation)
+    }
+
+    fn ping(&self, arguments: Vec<String>) -> Result<RespValue, String> {
+        match arguments.as_slice() {
+            [] => Ok(RespValue::Simple("PONG".to_string())),
+            [message] => Ok(RespValue::Bulk(message.to_string())),
+            _ => Err(wrong_arguments("PING")),
+        }
+    }
+
+    fn hello(
+        &mut self,
+        kvmap: &RwLock<KVMap>,
+        arguments: Vec<String>,
+    ) -> Result<RespValue, String> {
+        let version = match arguments.first() {
+            Some(version) => match version.as_str() {
+                "2" => Some(2),
+                "3" => Some(3),
+                _ => return Err("NOPROTO unsupported protocol version".to_string()),
+            },
+            None => None,
+        };
+        match arguments.get(1..) {
+            None | Some([]) => {}
+            Some([option, user, password]) if option.eq_ignore_ascii_case("AUTH") => {
+                self.auth(kvmap, user, password)?;
+            }
+            Some(_) => return Err("ERR syntax error".to_string()),
+        }
+        if let Some(version) = version {
+            self.version = version;
+        }
+
+        let field = |name: &str| RespValue::Bulk(name.to_string());
+        Ok(RespValue::Map(vec![
+            (field("server"), field("mycokv")),
+            (field("version"), field(env!("CARGO_PKG_VERSION"))),
+            (field("proto"), RespValue::Integer(self.version.into())),
+            (field("mode"), field("standalone")),
+        ]))
+    }
+
+    fn auth(
+        &mut self,
+        kvmap: &RwLock<KVMap>,
+        user: &str,
+        password: &str,
+    ) -> Result<RespValue, String> {
+        let operation = Operation::Auth(user.to_string(), password.to_string());
+        self.process(kvmap, operation).map_err(error)?;
+        Ok(RespValue::Simple("OK".to_string()))
+    }
+
+    fn get(&mut self, kvmap: &RwLock<KVMap>, key: &str) -> Result<RespValue, String> {
+        let key = parse_key(key)?;
+        match self.process(kvmap, Operation::Get(key.to_string())) {
+            // Strings are returned as they were set rather than quoted.
+            Ok(Reply::Value(Value::String(string))) => Ok(RespValue::Bulk(string)),
+            Ok(reply) => Ok(RespValue::Bulk(reply.to_string())),
+            Err(TransactionError::KeyNotFound(_)) => Ok(RespValue::Null),
+            Err(e) => Err(error(e)),
+        }
+    }
+
+    fn set(
+        &mut self,
+        kvmap: &RwLock<KVMap>,
+        command: &str,
+        arguments: Vec<String>,
+    ) -> Result<RespValue, String> {
+        let (key, value, options) = match arguments.as_slice() {
+            [key, value, options @ ..] => (parse_key(key)?, value, options),
+            _ => return Err(wrong_arguments(command)),
+        };
+        if value.contains(['\r', '\n']) {
+            return Err("ERR values cannot contain line breaks".to_string());
+        }
+
+        let duration = match options {
+            [] => None,
+            [unit, amount] => {
+                let amount = amount.parse::<i64>().ok().filter(|amount| *amount > 0);
+                let amount = amount.ok_or("ERR invalid expire time in 'set' command")?;
+                match unit.to_ascii_uppercase().as_str() {
+                    "EX" => Some(amount.saturating_mul(1000)),
+                    "PX" => Some(amount),
+                    _ => return Err("ERR syntax error".to_string()),
+                }
+            }
+            _ => return Err("ERR syntax error".to_string()),
+        };
+
+        // Only canonical numbers are stored as integers, so that values such as `02134`
+        // read back exactly as they were set.
+        let value = match value.parse::<i64>() {
+            Ok(number) if number.to_string() == *value => Value::Integer(number),
+            _ => Value::String(value.to_string()),
+        };
+        // Setting a value replaces any expiration the key had, as it does in Redis.
+        let expiration = match duration {
+            Some(duration) => Operation::Expire(key.to_string(), duration),
+            None => Operation::Persist(key.to_string()),
+        };
+        let put = Operation::Put(key.to_string(), value);
+        self.process(kvmap, Operation::Batch(vec![put, expiration]))
+            .map_err(error)?;
+
+        Ok(RespValue::Simple("OK".to_string()))
+    }
+
+    fn delete(&mut self, kvmap: &RwLock<KVMap>, keys: Vec<String>) -> Result<RespValue, String> {
+        let mut deleted = 0;
+        for key in keys {
+            let key = parse_key(&key)?;
+            match self.process(kvmap, Operation::Delete(key.to_string())) {
+                Ok(_) => deleted += 1,
+                Err(TransactionError::KeyNotFound(_)) => {}
+                Err(e) => return Err(error(e)),
+            }
+        }
+
+        Ok(RespValue::Integer(deleted))
+    }
+
+    fn expire(
+        &mut self,
+        kvmap: &RwLock<KVMap>,
+        key: &str,
+        seconds: &str,
+    ) -> Result<RespValue, String> {
+        let key = parse_key(key)?;
+        let seconds = seconds
+            .parse::<i64>()
+            .map_err(|_| "ERR value is not an integer or out of range".to_string())?;
+
+        let operation = Operation::Expire(key.to_string(), seconds.saturating_mul(1000));
+        match self.process(kvmap, operation) {
+            Ok(_) => Ok(RespValue::Integer(1)),
+            Err(TransactionError::KeyNotFound(_)) => Ok(RespValue::Integer(0)),
+            Err(e) => Err(error(e)),
+        }
+    }
+
+    fn ttl(&mut self, kvmap: &RwLock<KVMap>, key: &str) -> Result<RespValue, String> {
+        let key = parse_key(key)?;
+        match self.process(kvmap, Operation::Ttl(key.to_string())) {
+            Ok(Reply::Integer(-1)) => Ok(RespValue::Integer(-1)),
+            Ok(Reply::Integer(milliseconds)) => Ok(RespValue::Integer((milliseconds + 500) / 1000)),
+            Ok(_) => Err(error(TransactionError::InternalError)),
+            Err(TransactionError::KeyNotFound(_)) => Ok(RespValue::Integer(-2)),
+            Err(e) => Err(error(e)),
+        }
+    }
+
+    fn incr(&mut self, kvmap: &RwLock<KVMap>, key: &str) -> Result<RespValue, String> {
+        let key = parse_key(key)?;
+        match self.process(kvmap, Operation::Incr(key.to_string())) {
+            Ok(Reply::Value(Value::Integer(number))) => Ok(RespValue::Integer(number)),
+            Ok(_) => Err(error(TransactionError::InternalError)),
+            Err(TransactionError::OperationFailure(_)) => {
+                Err("ERR value is not an integer or out of range".to_string())
+            }
+            Err(e) => Err(error(e)),
+        }
+    }
+
+    fn keys(&mut self, kvmap: &RwLock<KVMap>, pattern: &str) -> Result<RespValue, String> {
+        let operation = Operation::Keys(parse_key(pattern)?.to_string(), PatternSyntax::Glob);
+        let result = self.process(kvmap, operation).map_err(error)?;
+        let keys: Vec<String> = serde_json::from_str(&result.to_string())
+            .map_err(|_| error(TransactionError::SerializationFailure))?;
+
+        Ok(RespValue::Array(
+            keys.into_iter().map(RespValue::Bulk).collect(),
+        ))
+    }
+}
+
+/// Keys are written to the log separated by spaces, so they cannot contain whitespace.
+fn parse_key(key: &str) -> Result<&str, String> {
+    match key.is_empty() || key.contains(char::is_whitespace) {
+        true => Err(format!("ERR invalid key '{}'", key)),
+        false => Ok(key),
+    }
+}
+
+fn wrong_arguments(command: &str) -> String {
+    format!(
+        "ERR wrong number of arguments for '{}' command",
+        command.to_lowercase()
+    )
+}
+
+fn error(error: TransactionError) -> String {
+    match error {
+        TransactionError::AuthenticationRequired => "NOAUTH Authentication required.".to_string(),
+        TransactionError::AuthenticationFailed => {
+            "WRONGPASS invalid username-password pair".to_string()
+        }
+        TransactionError::PermissionDenied(message) => format!("NOPERM {}", message),
+        error => format!("ERR {}", error.message()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{clock::ManualClock, wal::WriteAheadLog};
+
+    fn execute(connection: &mut RespConnection, kvmap: &RwLock<KVMap>, command: &str) -> RespValue {
+        let arguments = command.split(' ').map(str::to_string).collect();
+        connection.execute(kvmap, arguments)
+    }
+
+    #[test]
+    fn maps_redis_commands() {
+        let wal = Arc::new(Mutex::new(WriteAheadLog::new("log.resp.test.txt").unwrap()));
+        wal.lock().unwrap().clear().unwrap();
+        let clock = Arc::new(ManualClock::new(0));
+        let kvmap = RwLock::new(KVMap::with_clock(wal, clock.clone()));
+        let mut connection = RespConnection::new();
+        let bulk = |value: &str| RespValue::Bulk(value.to_string());
+
+        for (command, expected) in [
+            ("PING", RespValue::Simple("PONG".to_string())),
+            ("SET user.name Ada", RespValue::Simple("OK".to_string())),
+            (
+                "set user.visits 41 EX 10",
+                RespValue::Simple("OK".to_string()),
+            ),
+            ("GET user.name", bulk("Ada")),
+            ("GET user.missing", RespValue::Null),
+            ("INCR user.visits", RespValue::Integer(42)),
+            (
+                "INCR user.name",
+                RespValue::Error("ERR value is not an integer or out of range".to_string()),
+            ),
+            ("TTL user.visits", RespValue::Integer(10)),
+            ("TTL user.name", RespValue::Integer(-1)),
+            ("TTL user.missing", RespValue::Integer(-2)),
+            (
+                "SET session.token a EX 10",
+                RespValue::Simple("OK".to_string()),
+            ),
+            ("SET session.token b", RespValue::Simple("OK".to_string())),
+            ("TTL session.token", RespValue::Integer(-1)),
+            ("EXPIRE user.name 5", RespValue::Integer(1)),
+            ("EXPIRE user.missing 5", RespValue::Integer(0)),
+            (
+                "MGET user.name user.missing user.visits",
+                RespValue::Array(vec![bulk("Ada"), RespValue::Null, bulk("42")]),
+            ),
+            (
+                "KEYS user.*",
+                RespValue::Array(vec![bulk("user.name"), bulk("user.visits")]),
+            ),
+            ("DEL user.name user.missing", RespValue::Integer(1)),
+            (
+                r#"SET user.home C:\temp"1""#,
+                RespValue::Simple("OK".to_string()),
+            ),
+            ("GET user.home", bulk(r#"C:\temp"1""#)),
+            ("SET user.zip 02134", RespValue::Simple("OK".to_string())),
+            ("SET user.offset +5", RespValue::Simple("OK".to_string())),
+            ("GET user.zip", bulk("02134")),
+            ("GET user.offset", bulk("+5")),
+            (
+                "INCR user.zip",
+                RespValue::Error("ERR value is not an integer or out of range".to_string()),
+            ),
+            (
+                "SET user.name Ada PX 0",
+                RespValue::Error("ERR invalid expire time in 'set' command".to_string()),
+            ),
+            (
+                "GET",
+                RespValue::Error("ERR wrong number of arguments for 'get' command".to_string()),
+            ),
+            (
+                "FLUSHALL",
+                RespValue::Error("ERR unknown command 'flushall'".to_string()),
+            ),
+        ] {
+            assert_eq!(
+                execute(&mut connection, &kvmap, command),
+                expected,
+                "{}",
+                command
+            );
+        }
+
+        clock.advance(9400);
+        assert_eq!(
+            execute(&mut connection, &kvmap, "TTL user.visits"),
+            RespValue::Integer(1)
+        );
+    }
+
+    #[test]
+    fn negotiates_protocol_version() {
+        let wal = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let kvmap = RwLock::new(KVMap::new(wal));
+        let mut connection = RespConnection::new();
+
+        assert_eq!(connection.version(), 2);
+        match execute(&mut connection, &kvmap, "HELLO 3") {
+            RespValue::Map(fields) => assert!(
+                fields.contains(&(RespValue::Bulk("proto".to_string()), RespValue::Integer(3)))
+            ),
+            reply => panic!("expected a map, got {:?}", reply),
+        }
+        assert_eq!(connection.version(), 3);
+
+        assert_eq!(
+            execute(&mut connection, &kvmap, "HELLO 4"),
+            RespValue::Error("NOPROTO unsupported protocol version".to_string())
+        );
+        assert_eq!(connection.version(), 3);
+    }
+
+    #[test]
+    fn authenticates_with_auth_or_hello() {
+        let wal = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let kvmap = RwLock::new(KVMap::new(wal));
+        let access = Arc::new(AccessControl::new());
+        access
+            .execute(crate::acl::AclCommand::SetUser(
+                "root".to_string(),
+                "secret".to_string(),
+            ))
+            .unwrap();
+        let mut connection = RespConnection::with_access(Arc::clone(&access));
+
+        assert_eq!(
+            execute(&mut connection, &kvmap, "GET a"),
+            RespValue::Error("NOAUTH Authentication required.".to_string())
+        );
+        assert_eq!(
+            execute(&mut connection, &kvmap, "AUTH root wrong"),
+            RespValue::Error("WRONGPASS invalid username-password pair".to_string())
+        );
+        assert!(matches!(
+            execute(&mut connection, &kvmap, "HELLO 3 AUTH root secret"),
+            RespValue::Map(_)
+        ));
+        assert_eq!(connection.version(), 3);
+        assert_eq!(execute(&mut connection, &kvmap, "GET a"), RespValue::Null);
+    }
+}
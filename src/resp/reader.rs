@@ -0,0 +1,128 @@
+use std::io;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+/// The largest bulk string accepted in a command, matching Redis' default.
+const MAX_BULK_LENGTH: usize = 512 * 1024 * 1024;
+
+/// Reads the next command from the stream, either as an array of bulk strings or as an
+/// inline command separated by spaces. Returns `None` once the client disconnects.
+pub async fn read_command<R>(reader: &mut R) -> io::Result<Option<Vec<String>>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    loop {
+        let line = match read_line(reader).await? {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+
+        let count = match line.strip_prefix('*') {
+            Some(count) => parse_length(count)?,
+            None => {
+                let arguments: Vec<String> = line.split_whitespace().map(str::to_string).collect();
+                match arguments.is_empty() {
+                    true => continue,
+                    false => return Ok(Some(arguments)),
+                }
+            }
+        };
+
+        let mut arguments = Vec::with_capacity(count.min(1024));
+        for _ in 0..count {
+            arguments.push(read_bulk(reader).await?);
+        }
+        return Ok(Some(arguments));
+    }
+}
+
+async fn read_bulk<R>(reader: &mut R) -> io::Result<String>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let line = read_line(reader)
+        .await?
+        .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+    let length = line
+        .strip_prefix('$')
+        .ok_or_else(|| invalid_data(format!("expected '$', got '{}'", line)))?;
+    let length = parse_length(length)?;
+    if length > MAX_BULK_LENGTH {
+        return Err(invalid_data("invalid bulk length".to_string()));
+    }
+
+    let mut bulk = vec![0; length + 2];
+    reader.read_exact(&mut bulk).await?;
+    if !bulk.ends_with(b"\r\n") {
+        return Err(invalid_data("bulk string is not terminated".to_string()));
+    }
+    bulk.truncate(length);
+
+    String::from_utf8(bulk).map_err(|_| invalid_data("bulk string is not UTF-8".to_string()))
+}
+
+async fn read_line<R>(reader: &mut R) -> io::Result<Option<String>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line).await? == 0 {
+        return Ok(None);
+    }
+    while matches!(line.last(), Some(b'\n' | b'\r')) {
+        line.pop();
+    }
+
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| invalid_data("line is not UTF-8".to_string()))
+}
+
+fn parse_length(length: &str) -> io::Result<usize> {
+    length
+        .parse()
+        .map_err(|_| invalid_data(format!("invalid length '{}'", length)))
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn reads_array_and_inline_commands() {
+        let mut input: &[u8] = b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$5\r\nb c\r\n\r\n\r\nPING  hi\r\n";
+
+        assert_eq!(
+            read_command(&mut input).await.unwrap(),
+            Some(vec![
+                "SET".to_string(),
+                "a".to_string(),
+                "b c\r\n".to_string()
+            ])
+        );
+        assert_eq!(
+            read_command(&mut input).await.unwrap(),
+            Some(vec!["PING".to_string(), "hi".to_string()])
+        );
+        assert_eq!(read_command(&mut input).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_frames() {
+        let mut input: &[u8] = b"*1\r\n:1\r\n";
+        assert_eq!(
+            read_command(&mut input).await.unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let mut input: &[u8] = b"*1\r\n$3\r\nab";
+        assert_eq!(
+            read_command(&mut input).await.unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+}
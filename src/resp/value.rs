@@ -0,0 +1,81 @@
+/// A reply in the Redis serialization protocol.
+#[derive(Debug, PartialEq, Clone)]
+pub enum RespValue {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(String),
+    Null,
+    Array(Vec<RespValue>),
+    Map(Vec<(RespValue, RespValue)>),
+}
+
+impl RespValue {
+    /// Appends the reply to `buffer` in the given protocol version. RESP2 has no map or
+    /// null types, so maps are sent as flat arrays and nulls as null bulk strings.
+    pub fn encode(&self, version: u8, buffer: &mut Vec<u8>) {
+        match self {
+            RespValue::Simple(string) => push_line(buffer, '+', string),
+            RespValue::Error(message) => push_line(buffer, '-', message),
+            RespValue::Integer(number) => push_line(buffer, ':', &number.to_string()),
+            RespValue::Bulk(string) => {
+                push_line(buffer, '$', &string.len().to_string());
+                buffer.extend_from_slice(string.as_bytes());
+                buffer.extend_from_slice(b"\r\n");
+            }
+            RespValue::Null if version >= 3 => buffer.extend_from_slice(b"_\r\n"),
+            RespValue::Null => buffer.extend_from_slice(b"$-1\r\n"),
+            RespValue::Array(values) => {
+                push_line(buffer, '*', &values.len().to_string());
+                for value in values {
+                    value.encode(version, buffer);
+                }
+            }
+            RespValue::Map(pairs) => {
+                match version >= 3 {
+                    true => push_line(buffer, '%', &pairs.len().to_string()),
+                    false => push_line(buffer, '*', &(pairs.len() * 2).to_string()),
+                }
+                for (key, value) in pairs {
+                    key.encode(version, buffer);
+                    value.encode(version, buffer);
+                }
+            }
+        }
+    }
+}
+
+fn push_line(buffer: &mut Vec<u8>, prefix: char, line: &str) {
+    buffer.push(prefix as u8);
+    buffer.extend_from_slice(line.as_bytes());
+    buffer.extend_from_slice(b"\r\n");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn encode(value: RespValue, version: u8) -> String {
+        let mut buffer = Vec::new();
+        value.encode(version, &mut buffer);
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn encodes_for_each_version() {
+        let reply = RespValue::Array(vec![
+            RespValue::Bulk("a".to_string()),
+            RespValue::Null,
+            RespValue::Integer(-2),
+        ]);
+        assert_eq!(encode(reply.clone(), 2), "*3\r\n$1\r\na\r\n$-1\r\n:-2\r\n");
+        assert_eq!(encode(reply, 3), "*3\r\n$1\r\na\r\n_\r\n:-2\r\n");
+
+        let map = RespValue::Map(vec![(
+            RespValue::Bulk("proto".to_string()),
+            RespValue::Integer(3),
+        )]);
+        assert_eq!(encode(map.clone(), 2), "*2\r\n$5\r\nproto\r\n:3\r\n");
+        assert_eq!(encode(map, 3), "%1\r\n$5\r\nproto\r\n:3\r\n");
+    }
+}
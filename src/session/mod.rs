@@ -0,0 +1,430 @@
+use std::sync::{Arc, RwLock};
+
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{
+    acl::AccessControl,
+    errors::TransactionError,
+    kvmap::KVMap,
+    operation::{reply::Reply, Operation},
+    pubsub::{Message, Subscriptions},
+    snapshot::Snapshot,
+};
+
+/// How responses are written on a connection, negotiated with `HELLO`.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Protocol {
+    /// Results and error messages as they are, one per line.
+    #[default]
+    Text,
+    /// A JSON object per line stating whether the command succeeded, with the type of
+    /// its value or the code of its error.
+    Typed,
+}
+
+impl Protocol {
+    pub fn version(&self) -> u8 {
+        match self {
+            Protocol::Text => 1,
+            Protocol::Typed => 2,
+        }
+    }
+
+    pub fn from_version(version: u8) -> Option<Self> {
+        match version {
+            1 => Some(Protocol::Text),
+            2 => Some(Protocol::Typed),
+            _ => None,
+        }
+    }
+
+    /// Formats a message delivered to a subscription, naming the pattern it matched if it
+    /// was to a pattern.
+    pub fn format_message(&self, message: &Message, pattern: Option<&str>) -> String {
+        match (self, pattern) {
+            (Protocol::Text, None) => format!("MESSAGE {} {}", message.channel, message.payload),
+            (Protocol::Text, Some(pattern)) => format!(
+                "PMESSAGE {} {} {}",
+                pattern, message.channel, message.payload
+            ),
+            (Protocol::Typed, pattern) => {
+                let mut value = json!({ "channel": message.channel, "message": message.payload });
+                if let Some(pattern) = pattern {
+                    value["pattern"] = json!(pattern);
+                }
+                envelope(&Envelope::Success {
+                    ok: true,
+                    value_type: "message",
+                    value,
+                })
+            }
+        }
+    }
+
+    pub fn format(&self, result: Result<Reply, TransactionError>) -> String {
+        match (self, result) {
+            (Protocol::Text, Ok(reply)) => reply.to_string(),
+            (Protocol::Text, Err(error)) => error.message(),
+            (Protocol::Typed, Ok(reply)) => {
+                let (value_type, value) = reply.to_json();
+                envelope(&Envelope::Success {
+                    ok: true,
+                    value_type,
+                    value,
+                })
+            }
+            (Protocol::Typed, Err(error)) => {
+                let code = error.get_code();
+                let message = error.message();
+                let message = message
+                    .strip_prefix(&format!("{}: ", code))
+                    .unwrap_or(&message);
+                envelope(&Envelope::Failure {
+                    ok: false,
+                    code: &code,
+                    error: message,
+                })
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Envelope<'a> {
+    Success {
+        ok: bool,
+        #[serde(rename = "type")]
+        value_type: &'a str,
+        value: serde_json::Value,
+    },
+    Failure {
+        ok: bool,
+        code: &'a str,
+        error: &'a str,
+    },
+}
+
+fn envelope(envelope: &Envelope) -> String {
+    serde_json::to_string(envelope)
+        .unwrap_or_else(|_| TransactionError::SerializationFailure.message())
+}
+
+/// State kept for a single client connection between its commands.
+#[derive(Default)]
+pub struct Session {
+    snapshot: Option<Snapshot>,
+    protocol: Protocol,
+    subscriptions: Subscriptions,
+    access: Arc<AccessControl>,
+    user: Option<String>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session {
+            snapshot: None,
+            protocol: Protocol::Text,
+            subscriptions: Subscriptions::new(),
+            access: Arc::new(AccessControl::new()),
+            user: None,
+        }
+    }
+
+    /// Creates a session that must follow the users and rules of `access`.
+    pub fn with_access(access: Arc<AccessControl>) -> Self {
+        Session {
+            access,
+            ..Session::new()
+        }
+    }
+
+    /// Checks that the session's user may run the operation.
+    pub fn authorize(&self, operation: &Operation) -> Result<(), TransactionError> {
+        self.access.authorize(self.user.as_deref(), operation)
+    }
+
+    pub fn is_subscribed(&self) -> bool {
+        !self.subscriptions.is_empty()
+    }
+
+    /// Returns a line for each of the session's subscriptions the message is delivered to.
+    pub fn deliver(&self, message: &Message) -> Vec<String> {
+        self.subscriptions
+            .matches(message)
+            .into_iter()
+            .map(|pattern| self.protocol.format_message(message, pattern))
+            .collect()
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Parses and processes a request, returning the response in the connection's
+    /// protocol. A request starting with `#<id>` gets a response starting with the same
+    /// tag, so that pipelined responses can be matched to their requests.
+    pub fn execute(&mut self, kvmap: &RwLock<KVMap>, request: String) -> String {
+        let (tag, request) = split_tag(&request);
+        let result = Operation::parse(request.to_string())
+            .and_then(|operation| self.process(kvmap, operation));
+        let response = self.protocol.format(result);
+
+        match tag {
+            Some(tag) => format!("#{} {}", tag, response),
+            None => response,
+        }
+    }
+
+    /// Processes an operation for this connection. Reads use the session's snapshot while
+    /// one is open, and reads with their own snapshot run after the database is unlocked.
+    pub fn process(
+        &mut self,
+        kvmap: &RwLock<KVMap>,
+        operation: Operation,
+    ) -> Result<Reply, TransactionError> {
+        self.authorize(&operation)?;
+
+        match operation {
+            Operation::Auth(user, password) => {
+                self.access.authenticate(&user, &password)?;
+                self.user = Some(user);
+                Ok(Reply::ok())
+            }
+            Operation::Acl(command) => self.access.execute(command),
+            Operation::SnapshotBegin => {
+                self.snapshot = Some(take_snapshot(kvmap)?);
+                Ok(Reply::ok())
+            }
+            Operation::SnapshotEnd => match self.snapshot.take() {
+                Some(_) => Ok(Reply::ok()),
+                None => Err(TransactionError::OperationFailure(
+                    "no snapshot is open".to_string(),
+                )),
+            },
+            Operation::Snapshot(operation) => take_snapshot(kvmap)?.read(*operation),
+            Operation::Subscribe(channels) => {
+                self.subscriptions.subscribe(channels);
+                Ok(Reply::Integer(self.subscriptions.len() as i64))
+            }
+            Operation::Unsubscribe(channels) => {
+                self.subscriptions.unsubscribe(channels);
+                Ok(Reply::Integer(self.subscriptions.len() as i64))
+            }
+            Operation::PSubscribe(patterns) => {
+                self.subscriptions.psubscribe(patterns)?;
+                Ok(Reply::Integer(self.subscriptions.len() as i64))
+            }
+            Operation::PUnsubscribe(patterns) => {
+                self.subscriptions.punsubscribe(patterns);
+                Ok(Reply::Integer(self.subscriptions.len() as i64))
+            }
+            Operation::Hello(version) => {
+                if let Some(version) = version {
+                    self.protocol = Protocol::from_version(version).ok_or_else(|| {
+                        TransactionError::OperationFailure(format!(
+                            "protocol version {} is not supported",
+                            version
+                        ))
+                    })?;
+                }
+                let hello = json!({
+                    "server": "mycokv",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "protocol": self.protocol.version(),
+                });
+                Ok(Reply::Json(hello.to_string()))
+            }
+            operation => match &self.snapshot {
+                Some(snapshot) if operation.is_snapshot_read() => snapshot.read(operation),
+                _ => KVMap::process_shared(kvmap, operation),
+            },
+        }
+    }
+}
+
+fn split_tag(request: &str) -> (Option<&str>, &str) {
+    let tagged = request
+        .strip_prefix('#')
+        .map(|rest| rest.split_once(char::is_whitespace).unwrap_or((rest, "")));
+    match tagged {
+        Some((tag, request)) if !tag.is_empty() => (Some(tag), request),
+        _ => (None, request),
+    }
+}
+
+fn take_snapshot(kvmap: &RwLock<KVMap>) -> Result<Snapshot, TransactionError> {
+    KVMap::read_shared(kvmap, |kvmap| kvmap.snapshot())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::wal::WriteAheadLog;
+
+    fn process(session: &mut Session, kvmap: &RwLock<KVMap>, command: &str) -> String {
+        let operation = Operation::parse(command.to_string()).unwrap();
+        match session.process(kvmap, operation) {
+            Ok(reply) => reply.to_string(),
+            Err(error) => error.message(),
+        }
+    }
+
+    #[test]
+    fn reads_from_snapshot_until_ended() {
+        let wal = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let kvmap = RwLock::new(KVMap::new(wal));
+        let mut reader = Session::new();
+        let mut writer = Session::new();
+
+        process(&mut writer, &kvmap, "PUT x.a 1");
+        assert_eq!(process(&mut reader, &kvmap, "SNAPSHOT BEGIN"), "OK");
+        process(&mut writer, &kvmap, "PUT x.b 2");
+
+        assert_eq!(process(&mut reader, &kvmap, "GET x.*"), r#"{"a":1}"#);
+        assert_eq!(process(&mut writer, &kvmap, "GET x.*"), r#"{"a":1,"b":2}"#);
+        assert_eq!(process(&mut reader, &kvmap, "COUNT x.*"), "1");
+
+        assert_eq!(process(&mut reader, &kvmap, "SNAPSHOT END"), "OK");
+        assert_eq!(process(&mut reader, &kvmap, "GET x.*"), r#"{"a":1,"b":2}"#);
+        assert_eq!(
+            process(&mut reader, &kvmap, "SNAPSHOT END"),
+            "E11: Unable to complete operation - no snapshot is open"
+        );
+    }
+
+    #[test]
+    fn negotiates_typed_responses() {
+        let wal = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let kvmap = RwLock::new(KVMap::new(wal));
+        let mut session = Session::new();
+        let mut execute = |request: &str| session.execute(&kvmap, request.to_string());
+
+        assert_eq!(
+            execute("GET session.missing"),
+            "E09: Key session.missing not found"
+        );
+        assert_eq!(
+            execute("HELLO 2"),
+            r#"{"ok":true,"type":"object","value":{"protocol":2,"server":"mycokv","version":"0.1.0"}}"#
+        );
+        assert_eq!(
+            execute(r#"PUT session.name "E09: not an error""#),
+            r#"{"ok":true,"type":"status","value":"OK"}"#
+        );
+        assert_eq!(
+            execute("GET session.name"),
+            r#"{"ok":true,"type":"string","value":"E09: not an error"}"#
+        );
+        execute(r#"PUT session.path "C:\temp\"quoted\"""#);
+        assert_eq!(
+            execute("GET session.path"),
+            r#"{"ok":true,"type":"string","value":"C:\\temp\\\"quoted\\\""}"#
+        );
+        execute("PUT session.score 2.0");
+        assert_eq!(
+            execute("GET session.score"),
+            r#"{"ok":true,"type":"float","value":2.0}"#
+        );
+        assert_eq!(
+            execute("EXPIRE session.name 10s"),
+            r#"{"ok":true,"type":"status","value":"OK"}"#
+        );
+        assert_eq!(
+            execute("GET session.missing"),
+            r#"{"ok":false,"code":"E09","error":"Key session.missing not found"}"#
+        );
+        assert_eq!(
+            execute("HELLO 3"),
+            r#"{"ok":false,"code":"E11","error":"Unable to complete operation - protocol version 3 is not supported"}"#
+        );
+        assert_eq!(
+            execute("HELLO 1"),
+            r#"{"protocol":1,"server":"mycokv","version":"0.1.0"}"#
+        );
+    }
+
+    #[test]
+    fn delivers_to_subscriptions() {
+        let wal = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let kvmap = RwLock::new(KVMap::new(wal));
+        let mut session = Session::new();
+
+        assert!(!session.is_subscribed());
+        assert_eq!(process(&mut session, &kvmap, "SUBSCRIBE alerts"), "1");
+        assert_eq!(
+            process(&mut session, &kvmap, "PSUBSCRIBE __keyspace__.config.*"),
+            "2"
+        );
+        assert!(session.is_subscribed());
+
+        let message = Message::new("__keyspace__.config.theme", "put");
+        assert_eq!(
+            session.deliver(&message),
+            vec!["PMESSAGE __keyspace__.config.* __keyspace__.config.theme put"]
+        );
+
+        process(&mut session, &kvmap, "HELLO 2");
+        assert_eq!(
+            session.deliver(&Message::new("alerts", "disk is full")),
+            vec![
+                r#"{"ok":true,"type":"message","value":{"channel":"alerts","message":"disk is full"}}"#
+            ]
+        );
+        assert!(session.deliver(&Message::new("other", "hi")).is_empty());
+    }
+
+    #[test]
+    fn tags_responses() {
+        let wal = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let kvmap = RwLock::new(KVMap::new(wal));
+        let mut session = Session::new();
+        let mut execute = |request: &str| session.execute(&kvmap, request.to_string());
+
+        assert_eq!(execute("#1 PUT tagged.a 1"), "#1 1");
+        assert_eq!(execute("#req-2 GET tagged.*"), r#"#req-2 {"a":1}"#);
+        assert_eq!(execute("#3 NOPE"), "#3 E01: Command NOPE not recognized");
+        assert_eq!(execute("#4"), "#4 E14: Invalid command");
+        assert_eq!(execute("GET tagged.a"), "1");
+    }
+
+    #[test]
+    fn requires_authentication_once_users_exist() {
+        let wal = Arc::new(Mutex::new(WriteAheadLog::new("log.test.txt").unwrap()));
+        let kvmap = RwLock::new(KVMap::new(wal));
+        let access = Arc::new(AccessControl::new());
+        let mut admin = Session::with_access(Arc::clone(&access));
+        let mut guest = Session::with_access(access);
+
+        assert_eq!(process(&mut admin, &kvmap, "PUT open.a 1"), "1");
+        assert_eq!(process(&mut admin, &kvmap, "ACL SETUSER root secret"), "OK");
+        assert_eq!(
+            process(&mut admin, &kvmap, "GET open.a"),
+            "E18: Authentication required"
+        );
+        assert_eq!(
+            process(&mut guest, &kvmap, "AUTH root wrong"),
+            "E20: Invalid username or password"
+        );
+        assert_eq!(process(&mut admin, &kvmap, "AUTH root secret"), "OK");
+        assert_eq!(process(&mut admin, &kvmap, "GET open.a"), "1");
+
+        process(&mut admin, &kvmap, "ACL SETUSER guest pass");
+        process(&mut admin, &kvmap, "ACL GRANT guest open.* read");
+        assert_eq!(process(&mut guest, &kvmap, "AUTH guest pass"), "OK");
+        assert_eq!(process(&mut guest, &kvmap, "GET open.a"), "1");
+        assert_eq!(
+            process(&mut guest, &kvmap, "PUT open.a 2"),
+            "E19: Permission denied - write access to open.a is not allowed"
+        );
+
+        process(&mut admin, &kvmap, "ACL DELUSER guest");
+        assert_eq!(
+            process(&mut guest, &kvmap, "GET open.a"),
+            "E18: Authentication required"
+        );
+    }
+}
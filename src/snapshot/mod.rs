@@ -0,0 +1,165 @@
+use crate::{
+    aggregate::Aggregate,
+    errors::TransactionError,
+    operation::{order::Order, reply::Reply, Operation},
+    pattern::{Pattern, PatternSyntax},
+    query::Predicate,
+    radixtree::RadixTree,
+};
+
+/// A read-only view of the data at the moment it was taken, unaffected by later writes.
+#[derive(Clone)]
+pub struct Snapshot {
+    radix_tree: RadixTree,
+}
+
+impl Snapshot {
+    pub fn new(radix_tree: RadixTree) -> Self {
+        Snapshot { radix_tree }
+    }
+
+    /// Runs a read operation against the snapshot.
+    ///
+    /// # Errors
+    /// Returns an `OperationFailure` if the operation is not a read of the stored data.
+    ///
+    pub fn read(&self, operation: Operation) -> Result<Reply, TransactionError> {
+        let result = match operation {
+            Operation::Get(key) => return self.get(&key),
+            Operation::GetOrdered(key, order, limit) => self.get_ordered(&key, order, limit),
+            Operation::Keys(pattern, syntax) => self.keys(&pattern, syntax),
+            Operation::Scan(prefix, cursor, count) => self.scan(&prefix, cursor.as_deref(), count),
+            Operation::Range(start, end, order, limit) => self.range(&start, &end, order, limit),
+            Operation::Query(prefix, predicates) => self.query(&prefix, &predicates),
+            Operation::Aggregate(aggregate, key, strict) => {
+                return self.aggregate(aggregate, &key, strict)
+            }
+            _ => Err(TransactionError::OperationFailure(
+                "only reads can use a snapshot".to_string(),
+            )),
+        };
+
+        result.map(Reply::Json)
+    }
+
+    pub fn get(&self, key: &str) -> Result<Reply, TransactionError> {
+        let result = self.radix_tree.get(key);
+
+        result.map_err(|error| match error {
+            TransactionError::KeyNotFound(_) => TransactionError::KeyNotFound(key.to_string()),
+            error => error,
+        })
+    }
+
+    pub fn get_ordered(
+        &self,
+        key: &str,
+        order: Order,
+        limit: Option<usize>,
+    ) -> Result<String, TransactionError> {
+        let result = self.radix_tree.get_ordered(key, order, limit);
+
+        result.map_err(|error| match error {
+            TransactionError::KeyNotFound(_) => TransactionError::KeyNotFound(key.to_string()),
+            error => error,
+        })
+    }
+
+    pub fn range(
+        &self,
+        start: &str,
+        end: &str,
+        order: Order,
+        limit: Option<usize>,
+    ) -> Result<String, TransactionError> {
+        serde_json::to_string(&self.radix_tree.range(start, end, order, limit))
+            .map_err(|_| TransactionError::SerializationFailure)
+    }
+
+    pub fn query(
+        &self,
+        prefix: &str,
+        predicates: &[Predicate],
+    ) -> Result<String, TransactionError> {
+        self.radix_tree.query(prefix, predicates)
+    }
+
+    pub fn aggregate(
+        &self,
+        aggregate: Aggregate,
+        key: &str,
+        strict: bool,
+    ) -> Result<Reply, TransactionError> {
+        let not_found = |error| match error {
+            TransactionError::KeyNotFound(_) => TransactionError::KeyNotFound(key.to_string()),
+            error => error,
+        };
+        if aggregate == Aggregate::Count {
+            return self
+                .radix_tree
+                .count(key)
+                .map(|count| Reply::Integer(count as i64))
+                .map_err(not_found);
+        }
+
+        let values = self.radix_tree.values(key).map_err(not_found)?;
+
+        Ok(Reply::Value(aggregate.compute(values, strict)?))
+    }
+
+    pub fn keys(&self, pattern: &str, syntax: PatternSyntax) -> Result<String, TransactionError> {
+        let pattern = Pattern::new(pattern, syntax)?;
+        serde_json::to_string(&self.radix_tree.keys(&pattern))
+            .map_err(|_| TransactionError::SerializationFailure)
+    }
+
+    pub fn scan(
+        &self,
+        prefix: &str,
+        cursor: Option<&str>,
+        count: usize,
+    ) -> Result<String, TransactionError> {
+        self.radix_tree
+            .scan(prefix, cursor, count)?
+            .to_string()
+            .map_err(|_| TransactionError::SerializationFailure)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::operation::value::Value;
+
+    #[test]
+    fn is_unaffected_by_later_writes() {
+        let mut radix_tree = RadixTree::new();
+        radix_tree
+            .put("a.b".to_string(), Value::Integer(1))
+            .unwrap();
+        let snapshot = Snapshot::new(radix_tree.clone());
+
+        radix_tree
+            .put("a.b".to_string(), Value::Integer(2))
+            .unwrap();
+        radix_tree
+            .put("a.c".to_string(), Value::Integer(3))
+            .unwrap();
+        radix_tree.delete("a.b".to_string()).unwrap();
+
+        assert_eq!(
+            snapshot.get("a.*"),
+            Ok(Reply::Json(r#"{"b":1}"#.to_string()))
+        );
+        assert_eq!(
+            radix_tree.get("a.*"),
+            Ok(Reply::Json(r#"{"c":3}"#.to_string()))
+        );
+        assert_eq!(
+            snapshot.read(Operation::Delete("a.b".to_string())),
+            Err(TransactionError::OperationFailure(
+                "only reads can use a snapshot".to_string()
+            ))
+        );
+    }
+}
@@ -23,25 +23,19 @@ impl WriteAheadLog {
         })
     }
 
-    pub fn write(&mut self, operation: &Operation) -> Result<(), TransactionError> {
-        let output = match operation {
-            // Ignore get operations since they have no affect on db state
-            Operation::Get(_) => return Ok(()),
-
-            Operation::Put(key, value) => format!("PUT {} {}\n", key, value.to_string()),
-            Operation::Delete(key) => format!("DELETE {}\n", key),
-            Operation::Purge => {
-                self.clear()?;
-                String::from("")
-            }
-            Operation::Time => return Ok(()),
-            Operation::ExpireAt(expiration) => {
-                format!("EXPIREAT {} {}\n", expiration.key, expiration.timestamp)
-            }
+    /// Appends the operation to the log, prefixed with `@<timestamp>` so that replaying
+    /// the log can tell when it happened.
+    pub fn write(&mut self, operation: &Operation, timestamp: i64) -> Result<(), TransactionError> {
+        if let Operation::Purge = operation {
+            return self.clear();
+        }
+        let output = match command(operation)? {
+            Some(output) => output,
+            None => return Ok(()),
         };
 
         self.file
-            .write_all(output.as_bytes())
+            .write_all(format!("@{} {}\n", timestamp, output).as_bytes())
             .map_err(|error| TransactionError::LogWriteFail(error.to_string()))?;
 
         Ok(())
@@ -68,3 +62,159 @@ impl WriteAheadLog {
         Ok(())
     }
 }
+
+/// Formats the operation as the command that replays it, or returns `None` if it does not
+/// change the data.
+fn command(operation: &Operation) -> Result<Option<String>, TransactionError> {
+    let output = match operation {
+        // Ignore get operations since they have no affect on db state
+        Operation::Get(_) => return Ok(None),
+        Operation::GetOrdered(_, _, _) => return Ok(None),
+
+        Operation::Put(key, value) => format!("PUT {} {}", key, value),
+        Operation::Delete(key) => format!("DELETE {}", key),
+        // Purging clears the log rather than adding to it.
+        Operation::Purge => return Ok(None),
+        Operation::Time => return Ok(None),
+        Operation::Keys(_, _) => return Ok(None),
+        Operation::Scan(_, _, _) => return Ok(None),
+        Operation::Range(_, _, _, _) => return Ok(None),
+        Operation::Query(_, _) => return Ok(None),
+        Operation::Index(pattern) => format!("INDEX {}", pattern),
+        Operation::DropIndex(pattern) => format!("DROPINDEX {}", pattern),
+        Operation::Find(_, _) => return Ok(None),
+        Operation::Aggregate(_, _, _) => return Ok(None),
+        Operation::Info => return Ok(None),
+        Operation::Rename(from, to) => format!("RENAME {} {}", from, to),
+        Operation::Move(from, to) => format!("MOVE {}.* {}", from, to),
+        Operation::Copy(from, to, false) => format!("COPY {} {}", from, to),
+        Operation::Copy(from, to, true) => format!("COPY {} {} TTL", from, to),
+        Operation::Persist(key) => format!("PERSIST {}", key),
+        // Each write of the batch is logged as its own command within the one record.
+        Operation::Batch(operations) => {
+            let mut commands = Vec::new();
+            for operation in operations {
+                commands.extend(command(operation)?);
+            }
+            let commands = serde_json::to_string(&commands)
+                .map_err(|_| TransactionError::SerializationFailure)?;
+            format!("BATCH {}", commands)
+        }
+        Operation::History(_, _) => return Ok(None),
+        Operation::GetAsOf(_, _) => return Ok(None),
+        Operation::Snapshot(_) => return Ok(None),
+        Operation::SnapshotBegin => return Ok(None),
+        Operation::SnapshotEnd => return Ok(None),
+        Operation::Ttl(_) => return Ok(None),
+        Operation::Hello(_) => return Ok(None),
+        Operation::Publish(_, _) => return Ok(None),
+        // Users are kept apart from the data, and passwords must never be logged.
+        Operation::Auth(_, _) | Operation::Acl(_) => return Ok(None),
+        Operation::Subscribe(_)
+        | Operation::Unsubscribe(_)
+        | Operation::PSubscribe(_)
+        | Operation::PUnsubscribe(_) => return Ok(None),
+        // Relative expirations must be resolved against the clock before logging, or
+        // replaying the log would extend them.
+        Operation::Expire(_, _) => return Err(TransactionError::InternalError),
+        Operation::Incr(_) => return Err(TransactionError::InternalError),
+        Operation::ExpireAt(expiration) => {
+            format!("EXPIREAT {} {}ms", expiration.key, expiration.timestamp)
+        }
+    };
+
+    Ok(Some(output))
+}
+
+/// Parses a line of the log into the time it was written, if known, and its operation.
+/// Lines written before timestamps were logged have no `@<timestamp>` prefix.
+pub fn parse_entry(line: &str) -> Result<(Option<i64>, Operation), TransactionError> {
+    let (timestamp, command) = match line.strip_prefix('@') {
+        Some(rest) => {
+            let (timestamp, command) = rest.split_once(' ').unwrap_or((rest, ""));
+            let timestamp = timestamp
+                .parse::<i64>()
+                .map_err(|_| TransactionError::InvalidValue(timestamp.to_string()))?;
+            (Some(timestamp), command)
+        }
+        None => (None, line),
+    };
+
+    Ok((timestamp, Operation::parse(with_units(command))?))
+}
+
+/// Logs written before time units were required hold `EXPIREAT` timestamps as bare
+/// numbers of milliseconds, which commands no longer accept.
+fn with_units(command: &str) -> String {
+    let mut parts = command.split_whitespace();
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some("EXPIREAT"), Some(key), Some(timestamp), None)
+            if timestamp.parse::<i64>().is_ok() =>
+        {
+            format!("EXPIREAT {} {}ms", key, timestamp)
+        }
+        _ => command.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::operation::{expiration::Expiration, value::Value};
+
+    #[test]
+    fn parses_entries_with_and_without_timestamps() {
+        assert_eq!(
+            parse_entry("@1700000000000 PUT a 1"),
+            Ok((
+                Some(1700000000000),
+                Operation::Put("a".to_string(), Value::Integer(1))
+            ))
+        );
+        assert_eq!(
+            parse_entry("DELETE a"),
+            Ok((None, Operation::Delete("a".to_string())))
+        );
+        assert_eq!(
+            parse_entry(r#"@1 BATCH ["PUT a \"x\"","PERSIST a"]"#),
+            Ok((
+                Some(1),
+                Operation::Batch(vec![
+                    Operation::Put("a".to_string(), Value::String("x".to_string())),
+                    Operation::Persist("a".to_string()),
+                ])
+            ))
+        );
+        assert_eq!(
+            parse_entry("EXPIREAT a 1700000000000"),
+            Ok((
+                None,
+                Operation::ExpireAt(Expiration::new("a".to_string(), 1700000000000))
+            ))
+        );
+        assert_eq!(
+            parse_entry("@soon DELETE a"),
+            Err(TransactionError::InvalidValue("soon".to_string()))
+        );
+    }
+
+    #[test]
+    fn writes_batches_as_one_record() {
+        let mut wal = WriteAheadLog::new("log.batch.test.txt").unwrap();
+        wal.clear().unwrap();
+        let batch = Operation::Batch(vec![
+            Operation::Put("a".to_string(), Value::String("x  y".to_string())),
+            Operation::ExpireAt(Expiration::new("a".to_string(), 5000)),
+        ]);
+        wal.write(&batch, 1000).unwrap();
+
+        let lines: Vec<String> = wal.read_all_lines().unwrap().map(Result::unwrap).collect();
+        assert_eq!(
+            lines,
+            vec![r#"@1000 BATCH ["PUT a \"x  y\"","EXPIREAT a 5000ms"]"#]
+        );
+        assert_eq!(parse_entry(&lines[0]), Ok((Some(1000), batch)));
+
+        wal.clear().unwrap();
+    }
+}
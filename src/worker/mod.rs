@@ -1,11 +1,15 @@
-use std::{thread, time::Duration};
+use std::{sync::Arc, thread};
+
+use crate::clock::{Clock, SystemClock};
 
 pub struct Worker<F>
 where
     F: Fn() + Send + Clone + 'static,
 {
     task: F,
-    interval: u64,
+    interval: i64,
+    clock: Arc<dyn Clock>,
+    next_run: i64,
 }
 
 impl<F> Worker<F>
@@ -13,37 +17,100 @@ where
     F: Fn() + Send + Clone + 'static,
 {
     pub fn new(interval: u64, task: F) -> Self {
-        Worker { task, interval }
+        Worker::with_clock(interval, task, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(interval: u64, task: F, clock: Arc<dyn Clock>) -> Self {
+        let next_run = clock.now();
+        Worker {
+            task,
+            interval: interval as i64,
+            clock,
+            next_run,
+        }
+    }
+
+    /// Runs the task if it is due according to the worker's clock, and returns whether it
+    /// ran.
+    pub fn run_pending(&mut self) -> bool {
+        let now = self.clock.now();
+        if now < self.next_run {
+            return false;
+        }
+
+        (self.task)();
+        self.next_run = now + self.interval;
+        true
     }
 
     pub fn start(&self) -> thread::JoinHandle<()> {
-        let task = self.task.clone();
-        let interval = self.interval;
+        let mut worker = Worker {
+            task: self.task.clone(),
+            interval: self.interval,
+            clock: Arc::clone(&self.clock),
+            next_run: self.next_run,
+        };
 
         thread::spawn(move || loop {
-            task();
-            thread::sleep(Duration::from_millis(interval));
+            worker.run_pending();
+            worker.clock.sleep_until(worker.next_run);
         })
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::sync::{Arc, Mutex};
+    use std::sync::{mpsc, Mutex};
+
+    use crate::clock::ManualClock;
 
     use super::*;
 
     #[test]
     fn test_worker() {
+        let clock = Arc::new(ManualClock::new(0));
         let x = Arc::new(Mutex::new(0));
         let x_clone = Arc::clone(&x);
-        let worker = Worker::new(100, move || {
-            let mut x = x_clone.lock().unwrap();
-            *x += 1;
-        });
-        let worker_thread = worker.start();
-        thread::sleep(Duration::from_millis(500));
-        drop(worker_thread);
+        let mut worker = Worker::with_clock(
+            100,
+            move || {
+                let mut x = x_clone.lock().unwrap();
+                *x += 1;
+            },
+            clock.clone(),
+        );
+
+        assert!(worker.run_pending());
+        assert!(!worker.run_pending());
+
+        for _ in 0..4 {
+            clock.advance(99);
+            assert!(!worker.run_pending());
+            clock.advance(1);
+            assert!(worker.run_pending());
+        }
+
         assert_eq!(*x.lock().unwrap(), 5);
     }
+
+    #[test]
+    fn test_worker_thread_follows_clock() {
+        let clock = Arc::new(ManualClock::new(0));
+        let (sender, receiver) = mpsc::channel();
+        let sender = Arc::new(Mutex::new(sender));
+        let worker = Worker::with_clock(
+            100,
+            move || {
+                sender.lock().unwrap().send(()).unwrap_or(());
+            },
+            clock.clone(),
+        );
+        let _worker_thread = worker.start();
+
+        receiver.recv().unwrap();
+        for _ in 0..4 {
+            clock.advance(100);
+            receiver.recv().unwrap();
+        }
+    }
 }